@@ -6,4 +6,4 @@ ldPath="${glibcPath}/lib/ld-linux-aarch64.so.1"
 
 /nix/store/zxdwkzrhbzi1w0s3nsk579f5d55p81z7-patchelf-0.15.0/bin/patchelf --set-interpreter $ldPath /home/copepod/rgb-2025-unwrapped
 
-LD_LIBRARY_PATH=${ldLibPath} /home/copepod/rgb-2025-unwrapped
\ No newline at end of file
+LD_LIBRARY_PATH=${ldLibPath} /home/copepod/rgb-2025-unwrapped "$@"
\ No newline at end of file
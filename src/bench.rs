@@ -0,0 +1,351 @@
+//! `rgb-2025 bench [frames]` renders frames of the layout's LEDs as fast as it can,
+//! without any strips attached, to see what rendering costs on the Pi itself.
+//!
+//! Every frame is rendered with both [`RenderBackend`]s, and through the old pipeline,
+//! which cloned every worker's LEDs into a new `Vec` each frame, collected the colors
+//! over channels and handed them to the writers through a `Mutex`, so they can all be
+//! compared on the same hardware.
+//! Allocations are counted by [`CountingAllocator`], which main installs as the global
+//! allocator. Only built with the `bench` feature, so the robot's build keeps the system
+//! allocator: `cargo run --release --features bench -- bench`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::{
+        Arc, Barrier, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::spawn,
+    time::{Duration, Instant},
+};
+
+use palette::{Clamp, LinSrgb};
+use shark::shader::{FragOne, IntoShader, Shader, ShaderExt, primitives::time_rainbow};
+use smart_leds::{RGB8, SmartLedsWrite};
+
+use crate::{
+    renderer::{Output, RenderBackend, Renderer},
+    scene::{Scene, ZoneShader},
+    shaders::{flowy_rainbow, to_linsrgb},
+};
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting allocations
+pub struct CountingAllocator;
+
+// SAFETY: Everything is forwarded to the system allocator
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+/// A strip that throws its colors away
+pub struct NullStrip;
+impl SmartLedsWrite for NullStrip {
+    type Error = ();
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        for color in iterator {
+            black_box(color.into());
+        }
+        Ok(())
+    }
+}
+
+/// An LED as the old pipeline saw it
+#[derive(Clone)]
+struct OldLed {
+    index: usize,
+    pos: [f64; 3],
+    zone: usize,
+    along: f64,
+}
+
+struct OldCtx {
+    zone_shaders: Arc<Vec<ZoneShader>>,
+    leds: Vec<OldLed>,
+    time: f64,
+}
+
+/// The pipeline from before the renderer was made allocation free, minus mechanisms,
+/// calibration and writers
+struct OldPipeline {
+    leds: Vec<OldLed>,
+    zone_names: Vec<String>,
+    output_lens: Vec<usize>,
+    output_colors: Vec<Mutex<Vec<RGB8>>>,
+    num_slots: usize,
+
+    workers_barrier: Arc<Barrier>,
+    ctx_senders: Vec<Sender<OldCtx>>,
+    colors_receiver: Receiver<Vec<(usize, RGB8)>>,
+}
+impl OldPipeline {
+    fn new(num_workers: usize, outputs: &[Output]) -> Self {
+        let mut leds = Vec::new();
+        let mut zone_names: Vec<String> = Vec::new();
+        let mut output_lens = Vec::new();
+        let mut num_slots = 0;
+        for output in outputs {
+            let start = num_slots;
+            for zone in &output.zones {
+                let zone_index = zone_names
+                    .iter()
+                    .position(|name| *name == zone.name)
+                    .unwrap_or_else(|| {
+                        zone_names.push(zone.name.clone());
+                        zone_names.len() - 1
+                    });
+
+                for (point, along) in zone.leds.iter().zip(zone.along()) {
+                    if let Some(point) = point {
+                        leds.push(OldLed {
+                            index: num_slots,
+                            pos: [point.x, point.y, point.z],
+                            zone: zone_index,
+                            along,
+                        });
+                    }
+                    num_slots += 1;
+                }
+            }
+            output_lens.push(num_slots - start);
+        }
+
+        let workers_barrier = Arc::new(Barrier::new(num_workers + 1));
+        let (colors_sender, colors_receiver) = channel();
+        let ctx_senders = (0..num_workers)
+            .map(|_| {
+                let (ctx_sender, ctx_receiver) = channel::<OldCtx>();
+                let barrier = workers_barrier.clone();
+                let colors_sender = colors_sender.clone();
+                spawn(move || {
+                    loop {
+                        let ctx = ctx_receiver.recv().unwrap();
+                        let colors = ctx
+                            .leds
+                            .iter()
+                            .map(|led| {
+                                let color: LinSrgb<f64> = ctx.zone_shaders[led.zone]
+                                    .shade(led.pos, led.along, ctx.time)
+                                    .clamp();
+                                (
+                                    led.index,
+                                    RGB8::new(
+                                        (color.red * 256.0) as u8,
+                                        (color.green * 256.0) as u8,
+                                        (color.blue * 256.0) as u8,
+                                    ),
+                                )
+                            })
+                            .collect();
+
+                        colors_sender.send(colors).unwrap();
+                        barrier.wait();
+                    }
+                });
+                ctx_sender
+            })
+            .collect();
+
+        Self {
+            leds,
+            zone_names,
+            output_colors: output_lens.iter().map(|_| Mutex::new(Vec::new())).collect(),
+            output_lens,
+            num_slots,
+
+            workers_barrier,
+            ctx_senders,
+            colors_receiver,
+        }
+    }
+
+    fn render_scene(&self, scene: &Scene, time: f64) {
+        let num_workers = self.ctx_senders.len();
+        let zone_shaders = Arc::new(
+            self.zone_names
+                .iter()
+                .map(|name| scene.zone_shader(name).clone())
+                .collect::<Vec<_>>(),
+        );
+
+        let chunk_size = self.leds.len().div_ceil(num_workers);
+        for (i, sender) in self.ctx_senders.iter().enumerate() {
+            let start = (i * chunk_size).min(self.leds.len());
+            let end = (start + chunk_size).min(self.leds.len());
+            sender
+                .send(OldCtx {
+                    zone_shaders: zone_shaders.clone(),
+                    leds: self.leds[start..end].to_vec(),
+                    time,
+                })
+                .unwrap();
+        }
+        self.workers_barrier.wait();
+
+        let mut new_colors = vec![RGB8::default(); self.num_slots];
+        for _ in 0..num_workers {
+            for (i, color) in self.colors_receiver.recv().unwrap() {
+                new_colors[i] = color;
+            }
+        }
+        let mut start = 0;
+        for (colors, len) in self.output_colors.iter().zip(&self.output_lens) {
+            *colors.lock().unwrap() = new_colors[start..start + len].to_vec();
+            start += len;
+        }
+    }
+}
+
+/// Samples a rainbow this many times per LED, about as expensive as blurring one
+const EXPENSIVE_SAMPLES: usize = 32;
+
+/// A rainbow along the zone smeared over time, as a stand in for shaders like
+/// `volume_blur` that are much more expensive than the rest of the scene
+fn expensive_rainbow() -> impl Shader<FragOne> {
+    let rainbow = to_linsrgb(time_rainbow().scale_time(40.0));
+    (move |frag: FragOne| {
+        let mut sum = [0.0; 3];
+        for i in 0..EXPENSIVE_SAMPLES {
+            let color = rainbow.shade(FragOne {
+                pos: frag.pos,
+                time: frag.time - i as f64 * 0.002,
+            });
+            sum[0] += color.red;
+            sum[1] += color.green;
+            sum[2] += color.blue;
+        }
+        let [red, green, blue] = sum.map(|channel| channel / EXPENSIVE_SAMPLES as f64);
+        LinSrgb::new(red, green, blue)
+    })
+    .into_shader()
+}
+
+/// Timing of one run
+struct BenchResult {
+    mean: Duration,
+    max: Duration,
+    allocations: f64,
+    /// Mean time spent shading, if it's known
+    shading: Option<Duration>,
+}
+impl BenchResult {
+    /// Runs `render` for `frames` frames. It returns how long shading took if it knows.
+    fn measure(frames: usize, mut render: impl FnMut(f64) -> Option<Duration>) -> Self {
+        // Warm up, so buffers that grow on the first frames are left out
+        for frame in 0..10 {
+            render(frame as f64 / 100.0);
+        }
+
+        let mut frame_times = Vec::with_capacity(frames);
+        let mut shading = Some(Duration::ZERO);
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        for frame in 0..frames {
+            let start = Instant::now();
+            let frame_shading = render(frame as f64 / 100.0);
+            frame_times.push(start.elapsed());
+            shading = shading
+                .zip(frame_shading)
+                .map(|(total, frame)| total + frame);
+        }
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+        Self {
+            mean: frame_times.iter().sum::<Duration>() / frames as u32,
+            max: frame_times.iter().max().copied().unwrap_or_default(),
+            allocations: allocations as f64 / frames as f64,
+            shading: shading.map(|total| total / frames as u32),
+        }
+    }
+
+    fn print(&self, name: &str) {
+        let shading = match self.shading {
+            Some(shading) => format!(
+                ", {:.0}% shading",
+                shading.as_secs_f64() / self.mean.as_secs_f64() * 100.0
+            ),
+            None => String::new(),
+        };
+        println!(
+            "  {name:<13} {:>6}us mean, {:>6}us max, {:.1} allocations per frame{shading}",
+            self.mean.as_micros(),
+            self.max.as_micros(),
+            self.allocations,
+        );
+    }
+}
+
+/// Renders `frames` frames on the outputs from `outputs` with both [`RenderBackend`]s
+/// and with the old pipeline, and prints what a frame cost on each. The outputs should
+/// be on [`NullStrip`]s, so writing doesn't slow down rendering.
+///
+/// Frames are rendered with a rainbow on every zone, and again with the first zone much
+/// more expensive than the rest, to see how well the backends balance uneven work.
+pub fn run(outputs: impl Fn() -> Vec<Output>, threads: usize, frames: usize) {
+    let frames = frames.max(1);
+
+    let old_pipeline = OldPipeline::new(threads, &outputs());
+    println!(
+        "Rendering {} LEDs in {} slots for {frames} frames with {threads} threads",
+        old_pipeline.leds.len(),
+        old_pipeline.num_slots
+    );
+
+    let mut scenes = vec![("even", Scene::new(flowy_rainbow()))];
+    if let Some(zone) = old_pipeline.zone_names.first() {
+        scenes.push((
+            "uneven",
+            Scene::new(flowy_rainbow()).zone_along(zone.clone(), expensive_rainbow()),
+        ));
+    }
+
+    let mut workers = Renderer::new(RenderBackend::Workers(threads), outputs());
+    let mut rayon_renderer = Renderer::new(RenderBackend::Rayon(threads), outputs());
+    for (name, scene) in &scenes {
+        println!("{name}:");
+        BenchResult::measure(frames, |time| {
+            if let Err(err) = workers.render_scene(scene, time) {
+                eprintln!("{err}");
+            }
+            Some(workers.shading_time())
+        })
+        .print("workers");
+        BenchResult::measure(frames, |time| {
+            if let Err(err) = rayon_renderer.render_scene(scene, time) {
+                eprintln!("{err}");
+            }
+            Some(rayon_renderer.shading_time())
+        })
+        .print("rayon");
+        BenchResult::measure(frames, |time| {
+            old_pipeline.render_scene(scene, time);
+            None
+        })
+        .print("old pipeline");
+    }
+}
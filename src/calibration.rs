@@ -0,0 +1,163 @@
+//! Turning the linear colors from shaders into the values sent to a strip.
+//!
+//! Every output has its own [`Calibration`], so strips from different batches can be
+//! matched. It can be set per output in `layout.toml`:
+//!
+//! ```toml
+//! [output.calibration]
+//! gamma = 1.0
+//! white_point = [1.0, 0.9, 0.8]
+//! channel_gain = [1.0, 1.0, 1.0]
+//! min_brightness = 1
+//! ```
+
+use palette::LinSrgb;
+use serde::Deserialize;
+
+use crate::dither::{RGB16_MAX, Rgb16};
+
+/// Steps the linear input of a channel is quantized to before the lookup table. More than
+/// 8 bits, so dark colors keep their precision through the curve.
+const LUT_SIZE: usize = 4096;
+
+/// Input below this is off and isn't raised to `min_brightness`
+const OFF_THRESHOLD: f64 = 1.0 / LUT_SIZE as f64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Calibration {
+    /// Exponent of the transfer curve, applied after the white point. The renderer works
+    /// in linear light and the LEDs' PWM is linear too, so 1.0 is physically correct.
+    /// Above 1.0 darkens the midtones, which can look closer to what shaders were tuned
+    /// on.
+    pub gamma: f64,
+    /// Gain of red, green and blue in linear light, so white on this strip looks like
+    /// white on the others. The largest should be 1.0.
+    pub white_point: [f64; 3],
+    /// Gain of red, green and blue after the curve, e.g. to dim a brighter batch
+    pub channel_gain: [f64; 3],
+    /// Lowest value sent for a channel that isn't off, so dim colors don't round away
+    /// to black. Some strips don't light up at all at 1.
+    pub min_brightness: u8,
+}
+impl Default for Calibration {
+    /// Sends colors unchanged
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            white_point: [1.0; 3],
+            channel_gain: [1.0; 3],
+            min_brightness: 0,
+        }
+    }
+}
+impl Calibration {
+    /// The output value for a linear `value` on `channel` (0 = red, 1 = green, 2 = blue),
+    /// with the precision kept for dithering
+    pub fn channel(&self, channel: usize, value: f64) -> u16 {
+        let value = value.clamp(0.0, 1.0);
+        if value < OFF_THRESHOLD {
+            return 0;
+        }
+
+        let curved = (value * self.white_point[channel])
+            .clamp(0.0, 1.0)
+            .powf(self.gamma);
+        let output = (curved * self.channel_gain[channel] * RGB16_MAX as f64)
+            .round()
+            .clamp(0.0, RGB16_MAX as f64) as u16;
+
+        output.max((self.min_brightness as u16) << 8)
+    }
+
+    /// Compiles the calibration into a table, so applying it is only a lookup per channel
+    pub fn lut(&self) -> CalibrationLut {
+        let mut channels = [[0; LUT_SIZE]; 3];
+        for (channel, table) in channels.iter_mut().enumerate() {
+            for (i, output) in table.iter_mut().enumerate() {
+                *output = self.channel(channel, i as f64 / (LUT_SIZE - 1) as f64);
+            }
+        }
+
+        CalibrationLut { channels }
+    }
+}
+
+/// A [`Calibration`] compiled by [`Calibration::lut`]
+#[derive(Debug, Clone)]
+pub struct CalibrationLut {
+    channels: [[u16; LUT_SIZE]; 3],
+}
+impl CalibrationLut {
+    pub fn apply(&self, color: LinSrgb<f64>) -> Rgb16 {
+        let lookup = |channel: usize, value: f64| {
+            let index = (value.clamp(0.0, 1.0) * (LUT_SIZE - 1) as f64).round() as usize;
+            self.channels[channel][index]
+        };
+
+        [
+            lookup(0, color.red),
+            lookup(1, color.green),
+            lookup(2, color.blue),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibrations() -> [Calibration; 4] {
+        [
+            Calibration::default(),
+            Calibration {
+                gamma: 2.2,
+                ..Calibration::default()
+            },
+            Calibration {
+                white_point: [1.0, 0.9, 0.8],
+                channel_gain: [1.0, 0.5, 1.0],
+                ..Calibration::default()
+            },
+            Calibration {
+                gamma: 2.2,
+                min_brightness: 4,
+                ..Calibration::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn lut_is_monotonic() {
+        for calibration in calibrations() {
+            let lut = calibration.lut();
+            for table in &lut.channels {
+                assert!(
+                    table.windows(2).all(|pair| pair[0] <= pair[1]),
+                    "{calibration:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lut_runs_from_off_to_full() {
+        for calibration in calibrations() {
+            let lut = calibration.lut();
+            assert_eq!(lut.apply(LinSrgb::new(0.0, 0.0, 0.0)), [0; 3]);
+            // Out of range colors are clamped to the ends
+            assert_eq!(lut.apply(LinSrgb::new(-1.0, -1.0, -1.0)), [0; 3]);
+
+            let full = lut.apply(LinSrgb::new(1.0, 1.0, 1.0));
+            assert_eq!(full, lut.apply(LinSrgb::new(2.0, 2.0, 2.0)));
+            for (channel, value) in full.into_iter().enumerate() {
+                assert_eq!(value, calibration.channel(channel, 1.0), "{calibration:?}");
+            }
+        }
+
+        let full = Calibration::default()
+            .lut()
+            .apply(LinSrgb::new(1.0, 1.0, 1.0));
+        assert_eq!(full, [RGB16_MAX; 3]);
+    }
+}
@@ -0,0 +1,107 @@
+//! Temporal dithering, so fades and gradients don't step at low brightness.
+//!
+//! Shaders are calibrated into [`Rgb16`], which keeps 8 bits below what a strip can
+//! show. An output with dithering on sends the nearest 8 bit value below every channel
+//! and carries what was left over into the next frame, so over a few frames each LED
+//! averages out to its exact color. At ~100 FPS the flicker is too fast to see.
+
+use smart_leds::RGB8;
+
+/// A color in 8.8 fixed point. The high byte of every channel is what an 8 bit strip
+/// shows, the low byte is the fraction between that and the next step.
+pub type Rgb16 = [u16; 3];
+
+/// The most a channel of an [`Rgb16`] can be, 255 on the strip
+pub const RGB16_MAX: u16 = 255 << 8;
+
+pub fn from_rgb8(color: RGB8) -> Rgb16 {
+    [color.r, color.g, color.b].map(|channel| (channel as u16) << 8)
+}
+
+/// The nearest 8 bit color, for outputs without dithering
+pub fn round(color: Rgb16) -> RGB8 {
+    let [r, g, b] = color.map(|channel| ((channel.min(RGB16_MAX) as u32 + 0x80) >> 8) as u8);
+    RGB8::new(r, g, b)
+}
+
+/// Quantizes the colors of one output, frame after frame
+#[derive(Debug, Clone, Default)]
+pub struct Dither {
+    enabled: bool,
+    /// What every channel of every slot is owed from earlier frames, below one step
+    errors: Vec<[u16; 3]>,
+}
+impl Dither {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            errors: Vec::new(),
+        }
+    }
+
+    /// The 8 bit colors to send this frame
+    pub fn quantize<'a>(&'a mut self, colors: &'a [Rgb16]) -> impl Iterator<Item = RGB8> + 'a {
+        // The output was remapped, what was owed belongs to other LEDs now
+        if self.errors.len() != colors.len() {
+            self.errors = vec![[0; 3]; colors.len()];
+        }
+
+        let enabled = self.enabled;
+        colors
+            .iter()
+            .zip(self.errors.iter_mut())
+            .map(move |(color, error)| {
+                if !enabled {
+                    return round(*color);
+                }
+
+                let mut channels = [0; 3];
+                for (channel, output) in channels.iter_mut().enumerate() {
+                    // The error is below one step, so this stays below 256 steps
+                    let total = color[channel].min(RGB16_MAX) as u32 + error[channel] as u32;
+                    *output = (total >> 8) as u8;
+                    error[channel] = (total & 0xff) as u16;
+                }
+
+                RGB8::new(channels[0], channels[1], channels[2])
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dithering_averages_to_the_input() {
+        let colors = [[0x0000, 0x0001, 0x0080], [0x1234, 0x12ff, RGB16_MAX]];
+        let mut dither = Dither::new(true);
+
+        // Every fraction is a multiple of 1/256, so 256 frames carry nothing over
+        let mut sums = [[0u32; 3]; 2];
+        for _ in 0..256 {
+            for (sum, color) in sums.iter_mut().zip(dither.quantize(&colors)) {
+                sum[0] += color.r as u32;
+                sum[1] += color.g as u32;
+                sum[2] += color.b as u32;
+            }
+        }
+
+        let expected = colors.map(|color| color.map(u32::from));
+        assert_eq!(sums, expected);
+    }
+
+    #[test]
+    fn full_channels_dont_overflow() {
+        let colors = [[0xFFFF; 3], [RGB16_MAX + 0xff; 3]];
+        let mut dither = Dither::new(true);
+        for _ in 0..300 {
+            let frame: Vec<RGB8> = dither.quantize(&colors).collect();
+            assert_eq!(frame, [RGB8::new(255, 255, 255); 2]);
+        }
+
+        assert_eq!(round([0xFFFF; 3]), RGB8::new(255, 255, 255));
+        let frame: Vec<RGB8> = Dither::new(false).quantize(&colors).collect();
+        assert_eq!(frame, [RGB8::new(255, 255, 255); 2]);
+    }
+}
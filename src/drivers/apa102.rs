@@ -0,0 +1,106 @@
+use std::io;
+
+use smart_leds::{RGB8, SmartLedsWrite};
+
+use super::{ColorOrder, spi::SpiBus};
+
+pub const APA102_COLOR_ORDER: ColorOrder = ColorOrder::Bgr;
+
+/// APA102s are happy well past this, but long unterminated clock lines on a robot aren't
+const APA102_SPI_CLOCK_HZ: u32 = 4_000_000;
+
+/// Largest value of the 5 bit global brightness field
+pub const APA102_MAX_BRIGHTNESS: u8 = 0x1F;
+
+/// APA102/SK9822 on a clocked SPI bus (MOSI and SCLK).
+///
+/// Every LED carries a 5 bit global brightness next to its 8 bit channels.
+/// [`SmartLedsWrite::write`] uses the same brightness for every LED, see
+/// [`Apa102::write_with_brightness`] to set it per LED.
+pub struct Apa102<SPI> {
+    spi: SPI,
+    brightness: u8,
+    data: Vec<u8>,
+}
+impl<SPI: embedded_hal::spi::SpiBus> Apa102<SPI> {
+    /// `brightness` is the global brightness of every LED, up to [`APA102_MAX_BRIGHTNESS`]
+    pub fn new(spi: SPI, brightness: u8) -> Self {
+        Self {
+            spi,
+            brightness: brightness.min(APA102_MAX_BRIGHTNESS),
+            data: Vec::new(),
+        }
+    }
+
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness.min(APA102_MAX_BRIGHTNESS);
+    }
+
+    /// Writes a frame where every LED has its own global brightness
+    pub fn write_with_brightness(
+        &mut self,
+        leds: impl IntoIterator<Item = (RGB8, u8)>,
+    ) -> Result<(), SPI::Error> {
+        self.data.clear();
+
+        // Start frame
+        self.data.extend([0; 4]);
+
+        let mut num_leds = 0usize;
+        for (color, brightness) in leds {
+            self.data.extend([
+                0xE0 | brightness.min(APA102_MAX_BRIGHTNESS),
+                color.b,
+                color.g,
+                color.r,
+            ]);
+            num_leds += 1;
+        }
+
+        // The SK9822 latches on an extra 32 zero bits, and both chips need half a clock
+        // per LED past the last one for the data to reach the end of the strip
+        self.data.extend([0; 4]);
+        self.data
+            .extend(std::iter::repeat_n(0, num_leds.div_ceil(16)));
+
+        self.spi.write(&self.data)
+    }
+}
+impl Apa102<SpiBus> {
+    pub fn open(bus: &str, brightness: u8) -> io::Result<Self> {
+        Ok(Self::new(
+            SpiBus::open_with_speed(bus, APA102_SPI_CLOCK_HZ)?,
+            brightness,
+        ))
+    }
+
+    /// Most LEDs whose frame fits in a transfer of `max_transfer_bytes`
+    pub fn max_leds(max_transfer_bytes: usize) -> usize {
+        // Close to the answer, and at most one over since the end frame rounds up
+        let leds = max_transfer_bytes.saturating_sub(8) * 16 / 65;
+        if frame_bytes(leds) > max_transfer_bytes {
+            leds.saturating_sub(1)
+        } else {
+            leds
+        }
+    }
+}
+
+/// Bytes in a frame of `num_leds`, with the start and end frames
+fn frame_bytes(num_leds: usize) -> usize {
+    4 + 4 * num_leds + 4 + num_leds.div_ceil(16)
+}
+
+impl<SPI: embedded_hal::spi::SpiBus> SmartLedsWrite for Apa102<SPI> {
+    type Error = SPI::Error;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        let brightness = self.brightness;
+        self.write_with_brightness(iterator.into_iter().map(|color| (color.into(), brightness)))
+    }
+}
@@ -0,0 +1,299 @@
+//! A strip that only exists in memory, so the renderer can run without any hardware, e.g.
+//! in CI or on a laptop.
+//!
+//! A [`CaptureStrip`] records every frame written to it, with the time it was written.
+//! The frames can be read from any thread through its [`Captures`]. They're the colors
+//! the strip would have been sent, so they've been through the output's calibration,
+//! power limiting, dithering and color order. Outputs on a capture strip usually want
+//! `dither: false`, so the same shader gives the same colors every frame.
+//!
+//! Frames can also be recorded to a file, one per line: the seconds since the strip was
+//! created, then every LED as hex.
+//!
+//! ```text
+//! 0.010213 ff0000 ff0000 000000
+//! 0.020187 fe0100 ff0000 000000
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use smart_leds::{RGB8, SmartLedsWrite};
+
+/// A frame written to a [`CaptureStrip`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedFrame {
+    /// When the frame was written, since the strip was created
+    pub time: Duration,
+    pub colors: Vec<RGB8>,
+}
+
+#[derive(Debug, Default)]
+struct CaptureState {
+    frames: VecDeque<CapturedFrame>,
+    written: u64,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<CaptureState>,
+    written: Condvar,
+}
+
+/// The frames of a [`CaptureStrip`], readable from any thread
+#[derive(Debug, Clone)]
+pub struct Captures {
+    shared: Arc<Shared>,
+}
+impl Captures {
+    /// Every frame that's kept, oldest first
+    pub fn frames(&self) -> Vec<CapturedFrame> {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .frames
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Waits until `frames` frames were written in total, or `timeout` passed. Whether
+    /// they were.
+    pub fn wait_for(&self, frames: u64, timeout: Duration) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        let (state, _) = self
+            .shared
+            .written
+            .wait_timeout_while(state, timeout, |state| state.written < frames)
+            .unwrap();
+        state.written >= frames
+    }
+}
+
+/// A strip that records the frames written to it instead of sending them anywhere
+#[derive(Debug)]
+pub struct CaptureStrip {
+    shared: Arc<Shared>,
+    start: Instant,
+    /// Most frames kept in memory, or all of them if `None`
+    keep: Option<usize>,
+    file: Option<BufWriter<File>>,
+}
+impl CaptureStrip {
+    /// A strip keeping every frame in memory
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::default(),
+            start: Instant::now(),
+            keep: None,
+            file: None,
+        }
+    }
+
+    /// Only keeps the latest `frames` frames in memory, so a long run doesn't grow forever
+    pub fn keep_last(mut self, frames: usize) -> Self {
+        self.keep = Some(frames);
+        self
+    }
+
+    /// Also records every frame to the file at `path`, replacing it if it exists
+    pub fn record_to(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        self.file = Some(BufWriter::new(File::create(path)?));
+        Ok(self)
+    }
+
+    pub fn captures(&self) -> Captures {
+        Captures {
+            shared: self.shared.clone(),
+        }
+    }
+}
+impl Default for CaptureStrip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SmartLedsWrite for CaptureStrip {
+    /// Recording to the file failed. The frame is still kept in memory.
+    type Error = io::Error;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        let time = self.start.elapsed();
+        let colors: Vec<RGB8> = iterator.into_iter().map(Into::into).collect();
+
+        let recorded = match &mut self.file {
+            Some(file) => record_frame(file, time, &colors),
+            None => Ok(()),
+        };
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.frames.push_back(CapturedFrame { time, colors });
+        state.written += 1;
+        if let Some(keep) = self.keep {
+            while state.frames.len() > keep {
+                state.frames.pop_front();
+            }
+        }
+        drop(state);
+        self.shared.written.notify_all();
+
+        recorded
+    }
+}
+
+/// Appends a frame to `file` as a line in the format from the module docs
+fn record_frame(file: &mut impl Write, time: Duration, colors: &[RGB8]) -> io::Result<()> {
+    write!(file, "{:.6}", time.as_secs_f64())?;
+    for color in colors {
+        write!(file, " {:02x}{:02x}{:02x}", color.r, color.g, color.b)?;
+    }
+    writeln!(file)?;
+    // Flushed every frame, so the file is complete up to the latest frame if the process
+    // is killed
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use shark::{
+        point::Point,
+        shader::{FragThree, Shader},
+    };
+    use shrewnit::Meters;
+
+    use super::*;
+    use crate::{
+        calibration::Calibration,
+        dither,
+        network_tables::{CoralState, MovementState},
+        renderer::{Output, OutputConfig, RenderBackend, Renderer},
+        shaders::{boxtube_shader, to_linsrgb},
+        strips::Zone,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn write_frames(strip: &mut CaptureStrip, frames: u8) {
+        for frame in 0..frames {
+            strip
+                .write([RGB8::new(frame, 0, 0), RGB8::new(0, frame, 1)])
+                .unwrap();
+        }
+    }
+
+    /// Reads back a file written by `record_frame`
+    fn parse_recording(recording: &str) -> Vec<(f64, Vec<RGB8>)> {
+        recording
+            .lines()
+            .map(|line| {
+                let mut fields = line.split(' ');
+                let time = fields.next().unwrap().parse().unwrap();
+                let colors = fields
+                    .map(|color| {
+                        let color = u32::from_str_radix(color, 16).unwrap();
+                        RGB8::new((color >> 16) as u8, (color >> 8) as u8, color as u8)
+                    })
+                    .collect();
+                (time, colors)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn renderer_writes_the_boxtube_shader() {
+        const TIME: f64 = 1.25;
+        let shader = || boxtube_shader(CoralState::Held, MovementState::Driver, [0.0 * Meters; 2]);
+
+        let points: Vec<Point> = (0..20)
+            .map(|i| Point {
+                x: i as f64 * 0.05,
+                y: 0.3,
+                z: 0.1,
+            })
+            .collect();
+        let strip = CaptureStrip::new();
+        let captures = strip.captures();
+        let config = OutputConfig {
+            dither: false,
+            ..OutputConfig::default()
+        };
+        let zones = vec![Zone::new("box tube", points.clone())];
+        let mut renderer = Renderer::new(
+            RenderBackend::Workers(2),
+            vec![Output::new("box tube", strip, zones, config)],
+        );
+
+        renderer.render(shader(), TIME).unwrap();
+        assert!(captures.wait_for(1, TIMEOUT));
+
+        let lut = Calibration::default().lut();
+        let shader = to_linsrgb(shader());
+        let expected: Vec<RGB8> = points
+            .iter()
+            .map(|point| {
+                let pos = [point.x, point.y, point.z];
+                dither::round(lut.apply(shader.shade(FragThree { pos, time: TIME })))
+            })
+            .collect();
+        let frames = captures.frames();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].colors, expected);
+    }
+
+    #[test]
+    fn wait_for_counts_every_frame_written() {
+        let mut strip = CaptureStrip::new().keep_last(1);
+        let captures = strip.captures();
+        assert!(!captures.wait_for(1, Duration::from_millis(10)));
+
+        let writer = std::thread::spawn(move || write_frames(&mut strip, 3));
+        // Trimmed frames still count
+        assert!(captures.wait_for(3, TIMEOUT));
+        writer.join().unwrap();
+        assert!(!captures.wait_for(4, Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn keep_last_drops_the_oldest_frames() {
+        let mut strip = CaptureStrip::new().keep_last(2);
+        let captures = strip.captures();
+        write_frames(&mut strip, 5);
+
+        let frames = captures.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].colors[0], RGB8::new(3, 0, 0));
+        assert_eq!(frames[1].colors[0], RGB8::new(4, 0, 0));
+        assert!(frames[0].time <= frames[1].time);
+    }
+
+    #[test]
+    fn recorded_frames_read_back() {
+        let path = std::env::temp_dir().join(format!("capture-test-{}.txt", std::process::id()));
+        let mut strip = CaptureStrip::new().record_to(&path).unwrap();
+        let captures = strip.captures();
+        write_frames(&mut strip, 3);
+
+        let recording = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let recorded = parse_recording(&recording);
+        let frames = captures.frames();
+        assert_eq!(recorded.len(), frames.len());
+        for ((time, colors), frame) in recorded.iter().zip(&frames) {
+            assert!((time - frame.time.as_secs_f64()).abs() < 1e-6);
+            assert_eq!(colors, &frame.colors);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Single-wire ("clockless") LED protocols emulated over SPI MOSI.
+//!
+//! Every data bit is sent as a fixed-width symbol of SPI bits whose leading ones make up
+//! the high time, the same trick `ws2812_spi` uses for the WS2812.
+
+/// How a chipset's data bits map onto SPI bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolTiming {
+    pub spi_clock_hz: u32,
+    /// SPI bits per data bit
+    pub symbol_bits: usize,
+    /// Symbol for a 0 bit, in the low `symbol_bits` bits and sent MSB first
+    pub zero: u8,
+    /// Symbol for a 1 bit, in the low `symbol_bits` bits and sent MSB first
+    pub one: u8,
+    /// Low time after a frame that latches it
+    pub reset_micros: u32,
+}
+impl SymbolTiming {
+    /// Replaces `data` with the SPI bytes for `bytes` (already in wire order) followed
+    /// by the reset
+    pub fn encode_frame(&self, data: &mut Vec<u8>, bytes: impl IntoIterator<Item = u8>) {
+        data.clear();
+
+        let mut current = 0u8;
+        let mut current_bits = 0;
+        for byte in bytes {
+            for i in (0..8).rev() {
+                let symbol = if (byte >> i) & 1 != 0 {
+                    self.one
+                } else {
+                    self.zero
+                };
+
+                for j in (0..self.symbol_bits).rev() {
+                    current = (current << 1) | ((symbol >> j) & 1);
+                    current_bits += 1;
+
+                    if current_bits == 8 {
+                        data.push(current);
+                        current = 0;
+                        current_bits = 0;
+                    }
+                }
+            }
+        }
+        if current_bits != 0 {
+            data.push(current << (8 - current_bits));
+        }
+
+        data.extend(std::iter::repeat_n(0, self.reset_bytes()));
+    }
+
+    /// Most LEDs of `bytes_per_led` whose frame, with the reset, fits in `max_bytes`
+    pub fn max_leds(&self, bytes_per_led: usize, max_bytes: usize) -> usize {
+        let bits_per_led = bytes_per_led * 8 * self.symbol_bits;
+        max_bytes.saturating_sub(self.reset_bytes()) * 8 / bits_per_led
+    }
+
+    fn reset_bytes(&self) -> usize {
+        let reset_bits = self.reset_micros as u64 * self.spi_clock_hz as u64 / 1_000_000;
+        reset_bits.div_ceil(8) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMING: SymbolTiming = SymbolTiming {
+        spi_clock_hz: 3_200_000,
+        symbol_bits: 3,
+        zero: 0b100,
+        one: 0b110,
+        reset_micros: 80,
+    };
+
+    #[test]
+    fn max_leds_fill_the_transfer() {
+        let mut data = Vec::new();
+        for bytes_per_led in [3, 4] {
+            let max_leds = TIMING.max_leds(bytes_per_led, 4096);
+
+            TIMING.encode_frame(&mut data, vec![0xFF; max_leds * bytes_per_led]);
+            assert!(data.len() <= 4096);
+            TIMING.encode_frame(&mut data, vec![0xFF; (max_leds + 1) * bytes_per_led]);
+            assert!(data.len() > 4096);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use serde::Deserialize;
+use smart_leds::RGB8;
+
+/// Order of the channels on the wire.
+///
+/// The renderer only moves the color channels around. Where the white byte of an RGBW
+/// strip goes is up to its driver, see [`ColorOrder::white`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorOrder {
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+    /// RGBW, white after the color channels
+    Rgbw,
+    Rbgw,
+    Grbw,
+    Gbrw,
+    Brgw,
+    Bgrw,
+    /// RGBW, white before the color channels
+    Wrgb,
+    Wrbg,
+    Wgrb,
+    Wgbr,
+    Wbrg,
+    Wbgr,
+}
+
+/// Where an RGBW strip takes its white byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhitePosition {
+    First,
+    #[default]
+    Last,
+}
+
+impl ColorOrder {
+    /// Channel indices (0 = red, 1 = green, 2 = blue) in wire order
+    const fn channels(self) -> [usize; 3] {
+        match self {
+            ColorOrder::Rgb | ColorOrder::Rgbw | ColorOrder::Wrgb => [0, 1, 2],
+            ColorOrder::Rbg | ColorOrder::Rbgw | ColorOrder::Wrbg => [0, 2, 1],
+            ColorOrder::Grb | ColorOrder::Grbw | ColorOrder::Wgrb => [1, 0, 2],
+            ColorOrder::Gbr | ColorOrder::Gbrw | ColorOrder::Wgbr => [1, 2, 0],
+            ColorOrder::Brg | ColorOrder::Brgw | ColorOrder::Wbrg => [2, 0, 1],
+            ColorOrder::Bgr | ColorOrder::Bgrw | ColorOrder::Wbgr => [2, 1, 0],
+        }
+    }
+
+    /// Where the white byte goes, if this is an RGBW order
+    pub const fn white(self) -> Option<WhitePosition> {
+        match self {
+            ColorOrder::Rgb
+            | ColorOrder::Rbg
+            | ColorOrder::Grb
+            | ColorOrder::Gbr
+            | ColorOrder::Brg
+            | ColorOrder::Bgr => None,
+            ColorOrder::Rgbw
+            | ColorOrder::Rbgw
+            | ColorOrder::Grbw
+            | ColorOrder::Gbrw
+            | ColorOrder::Brgw
+            | ColorOrder::Bgrw => Some(WhitePosition::Last),
+            ColorOrder::Wrgb
+            | ColorOrder::Wrbg
+            | ColorOrder::Wgrb
+            | ColorOrder::Wgbr
+            | ColorOrder::Wbrg
+            | ColorOrder::Wbgr => Some(WhitePosition::First),
+        }
+    }
+
+    /// The color to hand a driver that sends channels in `driver_order`, so that the
+    /// wire carries `color` in this order
+    pub fn remap(self, color: RGB8, driver_order: ColorOrder) -> RGB8 {
+        if self.channels() == driver_order.channels() {
+            return color;
+        }
+
+        let color = [color.r, color.g, color.b];
+        let wire = self.channels().map(|channel| color[channel]);
+
+        let mut remapped = [0; 3];
+        for (byte, channel) in wire.into_iter().zip(driver_order.channels()) {
+            remapped[channel] = byte;
+        }
+
+        RGB8::new(remapped[0], remapped[1], remapped[2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [ColorOrder; 18] = [
+        ColorOrder::Rgb,
+        ColorOrder::Rbg,
+        ColorOrder::Grb,
+        ColorOrder::Gbr,
+        ColorOrder::Brg,
+        ColorOrder::Bgr,
+        ColorOrder::Rgbw,
+        ColorOrder::Rbgw,
+        ColorOrder::Grbw,
+        ColorOrder::Gbrw,
+        ColorOrder::Brgw,
+        ColorOrder::Bgrw,
+        ColorOrder::Wrgb,
+        ColorOrder::Wrbg,
+        ColorOrder::Wgrb,
+        ColorOrder::Wgbr,
+        ColorOrder::Wbrg,
+        ColorOrder::Wbgr,
+    ];
+
+    /// Bytes a driver sending in `order` puts on the wire for `color`
+    fn wire(color: RGB8, order: ColorOrder) -> [u8; 3] {
+        let color = [color.r, color.g, color.b];
+        order.channels().map(|channel| color[channel])
+    }
+
+    #[test]
+    fn remap_puts_the_strip_order_on_the_wire() {
+        let color = RGB8::new(1, 2, 3);
+        for order in ORDERS {
+            for driver in ORDERS {
+                assert_eq!(
+                    wire(order.remap(color, driver), driver),
+                    wire(color, order),
+                    "{order:?} through a {driver:?} driver"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn remap_round_trips() {
+        let color = RGB8::new(1, 2, 3);
+        for order in ORDERS {
+            for driver in ORDERS {
+                assert_eq!(
+                    driver.remap(order.remap(color, driver), order),
+                    color,
+                    "{order:?} through a {driver:?} driver"
+                );
+            }
+        }
+    }
+}
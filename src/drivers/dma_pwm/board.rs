@@ -0,0 +1,234 @@
+//! Raspberry Pi model detection.
+//!
+//! The peripherals sit at a different physical address on every SoC generation, and
+//! poking the wrong one through `/dev/mem` corrupts whatever happens to live there.
+
+use std::{fmt, fs, io};
+
+const MODEL_PATH: &str = "/proc/device-tree/model";
+const SOC_RANGES_PATH: &str = "/proc/device-tree/soc/ranges";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardModel {
+    /// BCM2835: Pi 1, Zero and Compute Module 1
+    Pi1,
+    /// BCM2836/BCM2837: Pi 2
+    Pi2,
+    /// BCM2837: Pi 3, Zero 2 and Compute Module 3
+    Pi3,
+    /// BCM2711: Pi 4, Pi 400 and Compute Module 4
+    Pi4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Board {
+    pub model: BoardModel,
+    /// Physical address the peripherals are mapped at through `/dev/mem`
+    pub peripheral_base: usize,
+    /// Frequency of PLLD, which the PWM clock is divided down from
+    pub plld_freq: u32,
+    /// DMA channel that isn't used by the firmware or kernel
+    pub dma_channel: u32,
+}
+
+#[derive(Debug)]
+pub enum BoardError {
+    /// The device tree couldn't be read, we probably aren't on a Pi
+    Io(io::Error),
+    /// A board without a BCM283x/BCM2711 style DMA and PWM, like the Pi 5 where PWM
+    /// lives behind the RP1
+    Unsupported(String),
+}
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoardError::Io(err) => write!(f, "failed to read the device tree: {err}"),
+            BoardError::Unsupported(model) => {
+                write!(f, "unsupported board for DMA/PWM output: {model}")
+            }
+        }
+    }
+}
+impl std::error::Error for BoardError {}
+impl From<io::Error> for BoardError {
+    fn from(err: io::Error) -> Self {
+        BoardError::Io(err)
+    }
+}
+
+impl BoardModel {
+    /// Classifies the contents of `/proc/device-tree/model`
+    pub fn from_model_string(model: &str) -> Result<Self, BoardError> {
+        let model = model.trim_end_matches('\0').trim();
+
+        let unsupported = || Err(BoardError::Unsupported(model.to_owned()));
+        if !model.starts_with("Raspberry Pi") {
+            return unsupported();
+        }
+
+        if model.contains("Pi 5") || model.contains("Compute Module 5") || model.contains("500") {
+            unsupported()
+        } else if model.contains("Pi 4")
+            || model.contains("Pi 400")
+            || model.contains("Compute Module 4")
+        {
+            Ok(BoardModel::Pi4)
+        } else if model.contains("Pi 3")
+            || model.contains("Zero 2")
+            || model.contains("Compute Module 3")
+        {
+            Ok(BoardModel::Pi3)
+        } else if model.contains("Pi 2") {
+            Ok(BoardModel::Pi2)
+        } else {
+            Ok(BoardModel::Pi1)
+        }
+    }
+
+    /// Where the peripherals live if the device tree doesn't say
+    pub const fn default_peripheral_base(self) -> usize {
+        match self {
+            BoardModel::Pi1 => 0x2000_0000,
+            BoardModel::Pi2 | BoardModel::Pi3 => 0x3F00_0000,
+            BoardModel::Pi4 => 0xFE00_0000,
+        }
+    }
+
+    pub const fn plld_freq(self) -> u32 {
+        match self {
+            BoardModel::Pi1 | BoardModel::Pi2 | BoardModel::Pi3 => 500_000_000,
+            BoardModel::Pi4 => 750_000_000,
+        }
+    }
+
+    /// Channel 10 on every board. The firmware leaves it free, while channel 5 is used by
+    /// the SD card driver on some kernels, which is why rpi_ws281x moved off it after
+    /// reports of SD card corruption.
+    pub const fn dma_channel(self) -> u32 {
+        10
+    }
+}
+
+impl Board {
+    /// Reads the board model and peripheral base out of the device tree
+    pub fn detect() -> Result<Self, BoardError> {
+        let model = BoardModel::from_model_string(&fs::read_to_string(MODEL_PATH)?)?;
+
+        let peripheral_base = fs::read(SOC_RANGES_PATH)
+            .ok()
+            .and_then(|ranges| peripheral_base_from_ranges(&ranges))
+            .unwrap_or(model.default_peripheral_base());
+
+        Ok(Self::for_model(model, peripheral_base))
+    }
+
+    pub const fn for_model(model: BoardModel, peripheral_base: usize) -> Self {
+        Self {
+            model,
+            peripheral_base,
+            plld_freq: model.plld_freq(),
+            dma_channel: model.dma_channel(),
+        }
+    }
+}
+
+/// Parses the parent bus address out of `/proc/device-tree/soc/ranges`, the same way
+/// `bcm_host_get_peripheral_address` does. The first cell is the child (bus) address,
+/// followed by a one cell parent address on the Pi 1-3, or two cells on the Pi 4 where
+/// the high cell is zero.
+fn peripheral_base_from_ranges(ranges: &[u8]) -> Option<usize> {
+    let cell = |i: usize| -> Option<u32> {
+        Some(u32::from_be_bytes(
+            ranges.get(i * 4..i * 4 + 4)?.try_into().ok()?,
+        ))
+    };
+
+    match cell(1)? {
+        0 => cell(2).map(|base| base as usize),
+        base => Some(base as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_are_classified() {
+        let models = [
+            ("Raspberry Pi Model B Rev 2\0", BoardModel::Pi1),
+            ("Raspberry Pi Zero W Rev 1.1\0", BoardModel::Pi1),
+            ("Raspberry Pi 2 Model B Rev 1.1\0", BoardModel::Pi2),
+            ("Raspberry Pi 3 Model B Plus Rev 1.3\0", BoardModel::Pi3),
+            ("Raspberry Pi Zero 2 W Rev 1.0\0", BoardModel::Pi3),
+            (
+                "Raspberry Pi Compute Module 3 Plus Rev 1.0\0",
+                BoardModel::Pi3,
+            ),
+            ("Raspberry Pi 4 Model B Rev 1.5\0", BoardModel::Pi4),
+            ("Raspberry Pi 400 Rev 1.0\0", BoardModel::Pi4),
+            ("Raspberry Pi Compute Module 4 Rev 1.0\0", BoardModel::Pi4),
+        ];
+        for (model, expected) in models {
+            assert_eq!(
+                BoardModel::from_model_string(model).unwrap(),
+                expected,
+                "{model:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn pi_5_and_other_boards_are_rejected() {
+        for model in [
+            "Raspberry Pi 5 Model B Rev 1.0\0",
+            "Raspberry Pi 500 Rev 1.0\0",
+            "Raspberry Pi Compute Module 5 Rev 1.0\0",
+            "Pine64 RockPro64 v2.1\0",
+        ] {
+            assert!(
+                matches!(
+                    BoardModel::from_model_string(model),
+                    Err(BoardError::Unsupported(_))
+                ),
+                "{model:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn peripheral_base_is_read_from_ranges() {
+        let ranges = |cells: &[u32]| -> Vec<u8> {
+            cells.iter().flat_map(|cell| cell.to_be_bytes()).collect()
+        };
+
+        // Pi 1: bus address, one cell parent address, size
+        assert_eq!(
+            peripheral_base_from_ranges(&ranges(&[0x7E00_0000, 0x2000_0000, 0x0100_0000])),
+            Some(0x2000_0000)
+        );
+        // Pi 2 and 3
+        assert_eq!(
+            peripheral_base_from_ranges(&ranges(&[0x7E00_0000, 0x3F00_0000, 0x0100_0000])),
+            Some(0x3F00_0000)
+        );
+        // Pi 4: two cell parent address with a zero high cell
+        assert_eq!(
+            peripheral_base_from_ranges(&ranges(&[0x7E00_0000, 0, 0xFE00_0000, 0x0180_0000])),
+            Some(0xFE00_0000)
+        );
+        assert_eq!(peripheral_base_from_ranges(&ranges(&[0x7E00_0000])), None);
+    }
+
+    #[test]
+    fn every_board_uses_dma_channel_10() {
+        for model in [
+            BoardModel::Pi1,
+            BoardModel::Pi2,
+            BoardModel::Pi3,
+            BoardModel::Pi4,
+        ] {
+            assert_eq!(Board::for_model(model, 0).dma_channel, 10);
+        }
+    }
+}
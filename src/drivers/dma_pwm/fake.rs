@@ -0,0 +1,173 @@
+//! In-memory stand-in for the peripherals, which hands out plain heap memory so control
+//! block generation and frame encoding can be inspected on machines that aren't a Pi.
+//! Only built for tests.
+
+use std::{alloc::Layout, io};
+
+use super::{
+    BUS_PERIPHERAL_BASE, Board, DMA_ACTIVE, DMA_END_FLAG, DMA_OFFSET, DMA_SRC_INC, DmaControlBlock,
+    DmaControlRegister, DmaMemoryAllocationHandle, DmaMemoryBacking, MailboxError, PAGE_SIZE,
+    PWM_FIF1_OFFSET, PWM_OFFSET, Peripherals, board::BoardModel,
+};
+
+/// Bus address the fake DMA memory starts at, in the uncached alias like the real thing
+const FAKE_BUS_BASE: u32 = 0xC000_0000;
+
+/// In-memory stand-in for the peripherals.
+///
+/// Register blocks are zeroed memory that nothing else touches, so whatever the driver
+/// writes can be read back. The DMA engine only moves when [`FakePeripherals::step_dma`]
+/// is called.
+pub struct FakePeripherals {
+    board: Board,
+    /// Memory backing each mapped peripheral, by offset from the peripheral base
+    peripherals: Vec<(usize, *mut u8, Layout)>,
+    /// Bus address, virtual address and size of every DMA allocation
+    dma_memory: Vec<(u32, *mut u8, usize)>,
+    next_bus_address: u32,
+}
+// Only raw pointers to memory owned by this struct or its allocation handles
+unsafe impl Send for FakePeripherals {}
+
+impl FakePeripherals {
+    /// Fake peripherals of a Pi 4
+    pub fn new() -> Self {
+        Self::for_board(Board::for_model(
+            BoardModel::Pi4,
+            BoardModel::Pi4.default_peripheral_base(),
+        ))
+    }
+
+    pub fn for_board(board: Board) -> Self {
+        Self {
+            board,
+            peripherals: Vec::new(),
+            dma_memory: Vec::new(),
+            next_bus_address: FAKE_BUS_BASE,
+        }
+    }
+
+    /// Memory backing the peripheral mapped at `offset`, if the driver mapped it
+    pub fn peripheral(&self, offset: usize) -> Option<*mut u8> {
+        self.peripherals
+            .iter()
+            .find(|(mapped_offset, _, _)| *mapped_offset == offset)
+            .map(|(_, memory, _)| *memory)
+    }
+
+    /// Translates a bus address inside fake DMA memory to a pointer to it
+    pub fn bus_to_virtual(&self, bus_address: u32) -> Option<*mut u8> {
+        self.dma_memory
+            .iter()
+            .find(|(bus, _, size)| (*bus..*bus + *size as u32).contains(&bus_address))
+            .map(|(bus, virt, _)| unsafe { virt.add((bus_address - bus) as usize) })
+    }
+
+    /// Runs the control block loaded into the board's DMA channel and loads the next
+    /// one, like the hardware would. Returns the words written to the PWM FIFO, other
+    /// transfers aren't simulated.
+    ///
+    /// # Safety
+    /// The DMA memory the control blocks point at must not have been freed.
+    pub unsafe fn step_dma(&self) -> Vec<u32> {
+        let channel = self.board.dma_channel;
+        let Some(dma_base) = self.peripheral(DMA_OFFSET) else {
+            return Vec::new();
+        };
+
+        unsafe {
+            let dma_reg: *mut DmaControlRegister = dma_base.add(channel as usize * 0x100).cast();
+
+            let cs = (&raw const (*dma_reg).cs).read_volatile();
+            let cb_addr = (&raw const (*dma_reg).cb_addr).read_volatile();
+            if cs & DMA_ACTIVE == 0 || cb_addr == 0 {
+                return Vec::new();
+            }
+
+            let cb = self
+                .bus_to_virtual(cb_addr)
+                .expect("control block outside of DMA memory")
+                .cast::<DmaControlBlock>()
+                .read_volatile();
+
+            let mut words = Vec::new();
+            if cb.dest_ad == (BUS_PERIPHERAL_BASE + PWM_OFFSET + PWM_FIF1_OFFSET) as u32 {
+                let source = self
+                    .bus_to_virtual(cb.source_ad)
+                    .expect("transfer source outside of DMA memory")
+                    .cast::<u32>();
+
+                for i in 0..cb.txfr_len as usize / size_of::<u32>() {
+                    let offset = if cb.ti & DMA_SRC_INC != 0 { i } else { 0 };
+                    words.push(source.add(offset).read_volatile());
+                }
+            }
+
+            (&raw mut (*dma_reg).cb_addr).write_volatile(cb.nextconbk);
+            if cb.nextconbk == 0 {
+                (&raw mut (*dma_reg).cs).write_volatile((cs & !DMA_ACTIVE) | DMA_END_FLAG);
+            }
+
+            words
+        }
+    }
+}
+
+impl Default for FakePeripherals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Peripherals for FakePeripherals {
+    fn board(&self) -> Board {
+        self.board
+    }
+
+    unsafe fn map_peripheral(&mut self, offset: usize, size: usize) -> io::Result<*mut u8> {
+        if let Some(memory) = self.peripheral(offset) {
+            return Ok(memory);
+        }
+
+        let layout =
+            Layout::from_size_align(size.div_ceil(PAGE_SIZE) * PAGE_SIZE, PAGE_SIZE).unwrap();
+        let memory = unsafe { std::alloc::alloc_zeroed(layout) };
+        if memory.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+
+        self.peripherals.push((offset, memory, layout));
+        Ok(memory)
+    }
+
+    fn alloc_dma_memory(&mut self, size: usize) -> Result<DmaMemoryAllocationHandle, MailboxError> {
+        let size = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+        let layout = Layout::from_size_align(size, PAGE_SIZE).unwrap();
+        let virtual_memory_address = unsafe { std::alloc::alloc_zeroed(layout) };
+        if virtual_memory_address.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+
+        let bus_memory_address = self.next_bus_address;
+        self.next_bus_address += size as u32;
+        self.dma_memory
+            .push((bus_memory_address, virtual_memory_address, size));
+
+        Ok(DmaMemoryAllocationHandle {
+            virtual_memory_address,
+            size,
+            bus_memory_address: bus_memory_address as *mut u8,
+
+            backing: DmaMemoryBacking::Heap(layout),
+        })
+    }
+}
+
+impl Drop for FakePeripherals {
+    fn drop(&mut self) {
+        for (_, memory, layout) in self.peripherals.drain(..) {
+            unsafe { std::alloc::dealloc(memory, layout) };
+        }
+    }
+}
@@ -1,106 +1,199 @@
 //! Dead-simple mailbox implementation adapted from https://github.com/raspberrypi/userland/blob/master/host_applications/linux/apps/hello_pi/hello_fft/mailbox.c
 
-use libc::{open, O_RDWR, O_SYNC};
+use std::{
+    fmt, io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use libc::{O_RDWR, O_SYNC, open};
 
 use crate::drivers::dma_pwm::PAGE_SIZE;
 
-const IOCTL_MBOX_PROPERTY: i32 = 0xC008_6400;
+const IOCTL_MBOX_PROPERTY: u32 = 0xC008_6400;
 
-pub type MailboxMemoryHandle = u32;
+/// Request/response code of a property buffer the firmware processed successfully
+const MBOX_RESPONSE_SUCCESS: u32 = 0x8000_0000;
 
-unsafe fn mailbox_property(fd: i32, buf: &mut [u32]) -> i32 {
-    let result = unsafe { libc::ioctl(fd, IOCTL_MBOX_PROPERTY, buf.as_mut_ptr()) };
+const TAG_ALLOCATE_MEMORY: u32 = 0x3000c;
+const TAG_LOCK_MEMORY: u32 = 0x3000d;
+const TAG_UNLOCK_MEMORY: u32 = 0x3000e;
+const TAG_RELEASE_MEMORY: u32 = 0x3000f;
 
-    if result < 0 {
-        panic!("ioctl failed: {}", result);
-    }
+pub type MailboxMemoryHandle = u32;
 
-    result
+#[derive(Debug)]
+pub enum MailboxError {
+    /// `/dev/vcio` couldn't be opened
+    Open(io::Error),
+    /// The property ioctl itself failed
+    Ioctl { tag: u32, source: io::Error },
+    /// The firmware didn't process the property buffer
+    Response { tag: u32, code: u32 },
+    /// The GPU refused to allocate `size` bytes, usually because gpu_mem is too small
+    AllocationRefused { size: usize },
+    /// The allocation couldn't be locked to a bus address
+    LockFailed { handle: MailboxMemoryHandle },
+    /// The firmware returned a nonzero `status` when unlocking the allocation
+    UnlockFailed {
+        handle: MailboxMemoryHandle,
+        status: u32,
+    },
+    /// The firmware returned a nonzero `status` when freeing the allocation
+    FreeFailed {
+        handle: MailboxMemoryHandle,
+        status: u32,
+    },
+    /// Mapping the locked memory through `/dev/mem` failed
+    MapFailed(io::Error),
 }
-
-/// Allocates memory and returns a handle to it
-pub unsafe fn alloc(fd: i32, size: usize, align: usize, flags: u32) -> MailboxMemoryHandle {
-    let mut prop_buffer = [0u32; 32];
-    // buffer size in bytes
-    prop_buffer[0] = (9 * 4) as u32;
-    // tag id
-    prop_buffer[2] = 0x3000c;
-    // buffer size
-    prop_buffer[3] = 12;
-    // data size
-    prop_buffer[4] = 12;
-    // raspberry pi themselves seem unsure? "(num bytes? or pages?)"
-    prop_buffer[5] = size as u32;
-    // alignment
-    prop_buffer[6] = align as u32;
-    // MEM_FLAG_L1_NONALLOCATING
-    prop_buffer[7] = flags;
-
-    unsafe { mailbox_property(fd, &mut prop_buffer) };
-
-    prop_buffer[5]
+impl fmt::Display for MailboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailboxError::Open(err) => write!(f, "failed to open /dev/vcio: {err}"),
+            MailboxError::Ioctl { tag, source } => {
+                write!(f, "mailbox ioctl for tag {tag:#x} failed: {source}")
+            }
+            MailboxError::Response { tag, code } => {
+                write!(f, "mailbox tag {tag:#x} failed with response {code:#x}")
+            }
+            MailboxError::AllocationRefused { size } => {
+                write!(f, "GPU refused to allocate {size} bytes")
+            }
+            MailboxError::LockFailed { handle } => {
+                write!(f, "failed to lock GPU memory handle {handle}")
+            }
+            MailboxError::UnlockFailed { handle, status } => {
+                write!(
+                    f,
+                    "failed to unlock GPU memory handle {handle}: status {status:#x}"
+                )
+            }
+            MailboxError::FreeFailed { handle, status } => {
+                write!(
+                    f,
+                    "failed to free GPU memory handle {handle}: status {status:#x}"
+                )
+            }
+            MailboxError::MapFailed(err) => write!(f, "failed to map GPU memory: {err}"),
+        }
+    }
 }
+impl std::error::Error for MailboxError {}
 
-/// Frees a handle to memory
-pub unsafe fn free(fd: i32, handle: MailboxMemoryHandle) -> u32 {
-    let mut prop_buffer = [0u32; 32];
-    // buffer size in bytes
-    prop_buffer[0] = (7 * 4) as u32;
-    // tag id
-    prop_buffer[2] = 0x3000f;
-    // buffer size
-    prop_buffer[3] = 4;
-    // data size
-    prop_buffer[4] = 4;
-    prop_buffer[5] = handle;
-
-    unsafe { mailbox_property(fd, &mut prop_buffer) };
-
-    prop_buffer[5]
+/// Open handle to the VideoCore property interface, closed on drop
+#[derive(Debug)]
+pub struct Mailbox {
+    fd: OwnedFd,
 }
+impl Mailbox {
+    pub fn open() -> Result<Self, MailboxError> {
+        let fd = unsafe { libc::open(c"/dev/vcio".as_ptr(), 0) };
 
-/// Locks a handle to a fixed bus address
-pub unsafe fn lock(fd: i32, handle: MailboxMemoryHandle) -> *mut u8 {
-    let mut prop_buffer = [0u32; 32];
-    // buffer size in bytes
-    prop_buffer[0] = (7 * 4) as u32;
-    // tag id
-    prop_buffer[2] = 0x3000d;
-    // buffer size
-    prop_buffer[3] = 4;
-    // data size
-    prop_buffer[4] = 4;
-    prop_buffer[5] = handle;
-    prop_buffer[6] = 0;
-
-    unsafe { mailbox_property(fd, &mut prop_buffer) };
-
-    prop_buffer[5] as *mut u8
-}
+        if fd < 0 {
+            return Err(MailboxError::Open(io::Error::last_os_error()));
+        }
+
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Sends a single tag with its request values and returns the response values
+    fn property<const N: usize>(
+        &self,
+        tag: u32,
+        request: [u32; N],
+    ) -> Result<[u32; N], MailboxError> {
+        let mut prop_buffer = [0u32; 32];
+        // buffer size in bytes
+        prop_buffer[0] = ((6 + N) * 4) as u32;
+        // tag id
+        prop_buffer[2] = tag;
+        // buffer size
+        prop_buffer[3] = (N * 4) as u32;
+        // data size
+        prop_buffer[4] = (N * 4) as u32;
+        prop_buffer[5..5 + N].copy_from_slice(&request);
+
+        let result = unsafe {
+            libc::ioctl(
+                self.fd.as_raw_fd(),
+                IOCTL_MBOX_PROPERTY as _,
+                prop_buffer.as_mut_ptr(),
+            )
+        };
+        if result < 0 {
+            return Err(MailboxError::Ioctl {
+                tag,
+                source: io::Error::last_os_error(),
+            });
+        }
+        if prop_buffer[1] != MBOX_RESPONSE_SUCCESS {
+            return Err(MailboxError::Response {
+                tag,
+                code: prop_buffer[1],
+            });
+        }
+
+        Ok(prop_buffer[5..5 + N].try_into().unwrap())
+    }
+
+    /// Allocates memory and returns a handle to it
+    pub fn alloc(
+        &self,
+        size: usize,
+        align: usize,
+        flags: u32,
+    ) -> Result<MailboxMemoryHandle, MailboxError> {
+        // raspberry pi themselves seem unsure? "(num bytes? or pages?)"
+        let [handle, _, _] =
+            self.property(TAG_ALLOCATE_MEMORY, [size as u32, align as u32, flags])?;
+
+        match handle {
+            0 => Err(MailboxError::AllocationRefused { size }),
+            handle => Ok(handle),
+        }
+    }
 
-/// Locks a handle to a fixed bus address
-pub unsafe fn unlock(fd: i32, handle: MailboxMemoryHandle) {
-    let mut prop_buffer = [0u32; 32];
-    // buffer size in bytes
-    prop_buffer[0] = (7 * 4) as u32;
-    // tag id
-    prop_buffer[2] = 0x3000e;
-    // buffer size
-    prop_buffer[3] = 4;
-    // data size
-    prop_buffer[4] = 4;
-    prop_buffer[5] = handle;
-    prop_buffer[6] = 0;
-
-    unsafe { mailbox_property(fd, &mut prop_buffer) };
+    /// Frees a handle to memory
+    pub fn free(&self, handle: MailboxMemoryHandle) -> Result<(), MailboxError> {
+        let [status] = self.property(TAG_RELEASE_MEMORY, [handle])?;
+
+        match status {
+            0 => Ok(()),
+            status => Err(MailboxError::FreeFailed { handle, status }),
+        }
+    }
+
+    /// Locks a handle to a fixed bus address
+    pub fn lock(&self, handle: MailboxMemoryHandle) -> Result<u32, MailboxError> {
+        let [bus_address] = self.property(TAG_LOCK_MEMORY, [handle])?;
+
+        match bus_address {
+            0 => Err(MailboxError::LockFailed { handle }),
+            bus_address => Ok(bus_address),
+        }
+    }
+
+    /// Unlocks a handle so the GPU may move the memory again
+    pub fn unlock(&self, handle: MailboxMemoryHandle) -> Result<(), MailboxError> {
+        let [status] = self.property(TAG_UNLOCK_MEMORY, [handle])?;
+
+        match status {
+            0 => Ok(()),
+            status => Err(MailboxError::UnlockFailed { handle, status }),
+        }
+    }
 }
 
-pub unsafe fn map_mem(base: usize, size: usize) -> *mut u8 {
+pub unsafe fn map_mem(base: usize, size: usize) -> Result<*mut u8, MailboxError> {
     let memory = unsafe { open(c"/dev/mem".as_ptr(), O_RDWR | O_SYNC) };
 
     if memory < 0 {
-        panic!("Failed to open /dev/mem");
+        return Err(MailboxError::MapFailed(io::Error::last_os_error()));
     }
+    // The mapping stays valid after the fd is closed
+    let memory = unsafe { OwnedFd::from_raw_fd(memory) };
 
     let offset = base % PAGE_SIZE;
     let base = base - offset;
@@ -112,19 +205,20 @@ pub unsafe fn map_mem(base: usize, size: usize) -> *mut u8 {
             size,
             libc::PROT_READ | libc::PROT_WRITE,
             libc::MAP_SHARED,
-            memory,
+            memory.as_raw_fd(),
             base as i64,
         )
     };
 
-    if result_ptr == -1isize as *mut libc::c_void {
-        panic!("Failed to map");
+    if result_ptr == libc::MAP_FAILED {
+        return Err(MailboxError::MapFailed(io::Error::last_os_error()));
     }
 
-    result_ptr as *mut u8
+    Ok(unsafe { result_ptr.cast::<u8>().add(offset) })
 }
 
-pub unsafe fn unmap_mem(ptr: *mut u8, size: usize) {
+/// Unmaps memory mapped by [`map_mem`]
+pub unsafe fn unmap_mem(ptr: *mut u8, size: usize) -> io::Result<()> {
     let offset = ptr as usize % PAGE_SIZE;
 
     let ptr = (ptr as usize - offset) as *mut u8;
@@ -133,16 +227,7 @@ pub unsafe fn unmap_mem(ptr: *mut u8, size: usize) {
     let result = unsafe { libc::munmap(ptr as *mut libc::c_void, size) };
 
     if result != 0 {
-        panic!("Failed to unmap");
+        return Err(io::Error::last_os_error());
     }
+    Ok(())
 }
-
-pub unsafe fn open_mailbox() -> i32 {
-    let fd = unsafe { libc::open(c"/dev/vcio".as_ptr(), 0) };
-
-    if fd < 0 {
-        panic!("Failed to open mailbox");
-    }
-
-    fd
-}
\ No newline at end of file
@@ -1,11 +1,20 @@
-use std::{ffi::c_void, thread::sleep, time::Duration, u32};
+use std::{fmt, io, sync::Arc, thread::sleep, time::Duration};
 
-use libc::{MAP_SHARED, O_RDWR, O_SYNC, PROT_READ, PROT_WRITE, mmap, open};
-use mailbox::open_mailbox;
+use libc::{MAP_SHARED, O_RDWR, O_SYNC, PROT_READ, PROT_WRITE, close, mmap, open};
+use smart_leds::{RGB8, SmartLedsWrite};
 
+pub use board::{Board, BoardError};
+pub use mailbox::{Mailbox, MailboxError, MailboxMemoryHandle};
+#[cfg(test)]
+pub use fake::FakePeripherals;
+pub use peripherals::{DevMem, Peripherals};
+
+mod board;
+#[cfg(test)]
+mod fake;
 mod mailbox;
+mod peripherals;
 
-const PHYISCAL_PERIPHERAL_BASE: usize = 0xFE000000;
 const BUS_PERIPHERAL_BASE: usize = 0x7E00_0000;
 
 const DMA_OFFSET: usize = 0x7000;
@@ -14,14 +23,13 @@ const GPIO_OFFSET: usize = 0x20_0000;
 const CM_OFFSET: usize = 0x10_1000;
 const SYSTEM_TIMER_OFFSET: usize = 0x3000;
 
-const DMA_CHANNEL: u32 = 5;
-
 const PAGE_SIZE: usize = 0x1000;
 
 const MEM_FLAG_L1_NONALLOCATING: u32 = (1 << 2) | (2 << 2);
 
 const DMA_NO_WIDE_BURSTS: u32 = 1 << 26;
 const DMA_WAIT_RESP: u32 = 1 << 3;
+const DMA_SRC_INC: u32 = 1 << 8;
 const DMA_DEST_DREQ: u32 = 1 << 6;
 const DMA_CHANNEL_ABORT: u32 = 1 << 30;
 const DMA_CHANNEL_RESET: u32 = 1 << 31;
@@ -33,14 +41,11 @@ const DMA_ACTIVE: u32 = 1 << 0;
 
 const PWM0_DREQ: u32 = 5;
 
-const PLLD_FREQ: u32 = 500_000_000;
 const PLLD_DIV: u32 = 5;
 
-const PLLD_ACTUAL_FREQ: u32 = PLLD_FREQ / PLLD_DIV;
-
 // what in the silly
 const CM_PASSWORD: u32 = 0x5A << 24;
-/// Phase-locked-loop (500Mhz clock)
+/// Phase-locked-loop (500Mhz clock, 750Mhz on the Pi 4)
 const CM_SOURCE_PLLD: u32 = 6;
 const CM_KILL: u32 = 1 << 5;
 const CM_ENABLE: u32 = 1 << 4;
@@ -55,6 +60,28 @@ const PWM_ENABLE_PWEN1: u32 = 1 << 0;
 const PWM_MODE1_ENABLE_SERIALIZER: u32 = 1 << 1;
 const PWM_MSEN1: u32 = 1 << 7;
 
+/// PWM FIFO input register, relative to the PWM peripheral base
+const PWM_FIF1_OFFSET: usize = 0x18;
+
+/// GPIO function select value routing PWM0 channel 1 to GPIO 18
+const GPIO_FSEL_ALT5: u32 = 0b010;
+const PWM0_GPIO: u32 = 18;
+
+/// Serializer bit rate. Every WS2812 data bit is sent as a 3 bit symbol, so each
+/// serializer bit lasts ~416ns.
+const WS2812_PWM_FREQ: u32 = 2_400_000;
+const WS2812_SYMBOL_BITS: usize = 3;
+/// 832ns high, 416ns low
+const WS2812_SYMBOL_ONE: u32 = 0b110;
+/// 416ns high, 832ns low
+const WS2812_SYMBOL_ZERO: u32 = 0b100;
+/// Newer WS2812B revisions need >280us of low to latch, older ones only 50us
+const WS2812_RESET_MICROS: usize = 300;
+
+const WS2812_BITS_PER_LED: usize = 24 * WS2812_SYMBOL_BITS;
+const WS2812_RESET_WORDS: usize =
+    (WS2812_RESET_MICROS * WS2812_PWM_FREQ as usize / 1_000_000).div_ceil(32);
+
 /// DMA control block linked list element
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -77,50 +104,85 @@ struct DmaControlBlock {
 /// Handle to a allocated memory fit for DMA transfers
 /// This memory should remain cache-coherent and locked to a fixed bus address
 /// via the mailbox property interface.
-struct DmaMemoryAllocationHandle {
+///
+/// The memory is unmapped, unlocked and freed exactly once, when the handle is dropped.
+pub struct DmaMemoryAllocationHandle {
     virtual_memory_address: *mut u8,
     size: usize,
     bus_memory_address: *mut u8,
-    mailbox_handle: u32,
 
-    mailbox_fd: i32,
+    backing: DmaMemoryBacking,
+}
+/// Where the memory behind a [`DmaMemoryAllocationHandle`] came from
+enum DmaMemoryBacking {
+    /// VideoCore memory allocated through the mailbox
+    Mailbox {
+        mailbox: Arc<Mailbox>,
+        handle: MailboxMemoryHandle,
+    },
+    /// Heap memory standing in for VideoCore memory, see [`FakePeripherals`]
+    #[cfg(test)]
+    Heap(std::alloc::Layout),
 }
 impl DmaMemoryAllocationHandle {
-    pub unsafe fn alloc(mailbox_fd: i32, size: usize) -> Self {
+    pub fn alloc(mailbox: &Arc<Mailbox>, size: usize) -> Result<Self, MailboxError> {
         let size = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
 
-        let mailbox_handle =
-            unsafe { mailbox::alloc(mailbox_fd, size, PAGE_SIZE, MEM_FLAG_L1_NONALLOCATING) };
-        let bus_memory_address = unsafe { mailbox::lock(mailbox_fd, mailbox_handle) };
+        let handle = mailbox.alloc(size, PAGE_SIZE, MEM_FLAG_L1_NONALLOCATING)?;
+        let bus_memory_address = match mailbox.lock(handle) {
+            Ok(bus_memory_address) => bus_memory_address,
+            Err(err) => {
+                _ = mailbox.free(handle);
+                return Err(err);
+            }
+        };
         let virtual_memory_address =
-            unsafe { mailbox::map_mem(bus_addr_to_phys_addr(bus_memory_address as _) as _, size) };
-
-        Self {
+            match unsafe { mailbox::map_mem(bus_addr_to_phys_addr(bus_memory_address) as _, size) }
+            {
+                Ok(virtual_memory_address) => virtual_memory_address,
+                Err(err) => {
+                    _ = mailbox.unlock(handle);
+                    _ = mailbox.free(handle);
+                    return Err(err);
+                }
+            };
+
+        Ok(Self {
             virtual_memory_address,
             size,
-            bus_memory_address,
-            mailbox_handle,
+            bus_memory_address: bus_memory_address as _,
 
-            mailbox_fd,
-        }
-    }
-
-    unsafe fn _free(&mut self) {
-        unsafe {
-            mailbox::unmap_mem(self.virtual_memory_address, self.size);
-            mailbox::unlock(self.mailbox_fd, self.mailbox_handle);
-            mailbox::free(self.mailbox_fd, self.mailbox_handle);
-        }
-    }
-
-    pub unsafe fn free(mut self) {
-        unsafe { self._free() };
+            backing: DmaMemoryBacking::Mailbox {
+                mailbox: mailbox.clone(),
+                handle,
+            },
+        })
     }
 }
 
 impl Drop for DmaMemoryAllocationHandle {
     fn drop(&mut self) {
-        unsafe { self._free() };
+        match &self.backing {
+            DmaMemoryBacking::Mailbox { mailbox, handle } => {
+                // Panicking here could abort the process while unwinding, and there's
+                // nothing left to retry with anyway
+                if let Err(err) =
+                    unsafe { mailbox::unmap_mem(self.virtual_memory_address, self.size) }
+                {
+                    eprintln!("Failed to unmap DMA memory: {err}");
+                }
+                if let Err(err) = mailbox.unlock(*handle) {
+                    eprintln!("{err}");
+                }
+                if let Err(err) = mailbox.free(*handle) {
+                    eprintln!("{err}");
+                }
+            }
+            #[cfg(test)]
+            DmaMemoryBacking::Heap(layout) => unsafe {
+                std::alloc::dealloc(self.virtual_memory_address, *layout)
+            },
+        }
     }
 }
 
@@ -197,14 +259,18 @@ const fn bus_addr_to_phys_addr(bus_addr: u32) -> u32 {
     bus_addr & !0xC0000000
 }
 
-unsafe fn map_peripheral(offset: usize, size: usize) -> *mut u8 {
+/// Maps the peripheral at `offset` from `peripheral_base`, see [`Board::peripheral_base`].
+/// The mapping has to be unmapped with `munmap`.
+unsafe fn map_peripheral(
+    peripheral_base: usize,
+    offset: usize,
+    size: usize,
+) -> io::Result<*mut u8> {
     let memory = unsafe { open(c"/dev/mem".as_ptr(), O_RDWR | O_SYNC) };
     if memory < 0 {
-        panic!("Failed to open /dev/mem");
+        return Err(io::Error::last_os_error());
     }
 
-    dbg!(PHYISCAL_PERIPHERAL_BASE + offset);
-
     let result_ptr = unsafe {
         mmap(
             std::ptr::null_mut(),
@@ -212,15 +278,18 @@ unsafe fn map_peripheral(offset: usize, size: usize) -> *mut u8 {
             PROT_READ | PROT_WRITE,
             MAP_SHARED,
             memory,
-            (PHYISCAL_PERIPHERAL_BASE + offset) as i64,
+            (peripheral_base + offset) as i64,
         )
     };
+    let mapped = if result_ptr == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result_ptr.cast())
+    };
 
-    if result_ptr == -1isize as *mut c_void {
-        panic!("Failed to map peripheral");
-    }
-
-    result_ptr.cast()
+    // The mapping stays valid without the file descriptor
+    unsafe { close(memory) };
+    mapped
 }
 
 #[inline]
@@ -246,10 +315,18 @@ const unsafe fn nth_cb_bus_address(
     .cast()
 }
 
-unsafe fn enable_hardware_timer(cm_ctrl: *mut ClockManagerControlRegister) {
+/// Sets the function select bits of a GPIO pin
+unsafe fn set_gpio_function(gpio_base: *mut u32, pin: u32, function: u32) {
     unsafe {
-        dbg!(cm_ctrl);
+        let fsel = gpio_base.add((pin / 10) as usize);
+        let shift = (pin % 10) * 3;
 
+        fsel.write_volatile((fsel.read_volatile() & !(0b111 << shift)) | (function << shift));
+    }
+}
+
+unsafe fn enable_hardware_timer(cm_ctrl: *mut ClockManagerControlRegister, divisor: u32) {
+    unsafe {
         // Kill clock
         (&raw mut (*cm_ctrl).gp0_ctl).write_volatile(CM_PASSWORD | 0);
         
@@ -259,15 +336,13 @@ unsafe fn enable_hardware_timer(cm_ctrl: *mut ClockManagerControlRegister) {
         // Set clock source to PLLD
         (&raw mut (*cm_ctrl).gp0_ctl).write_volatile(CM_PASSWORD | CM_SOURCE_PLLD);
         sleep(Duration::from_micros(10));
-        // Set divisor to 5 to end with an effective frequency of 100MHz
-        (&raw mut (*cm_ctrl).gp0_div).write_volatile(CM_PASSWORD | (PLLD_DIV << 12));
+        // Set the integer divisor, e.g. 5 to end with an effective frequency of 100MHz
+        (&raw mut (*cm_ctrl).gp0_div).write_volatile(CM_PASSWORD | (divisor << 12));
         sleep(Duration::from_micros(10));
         // Enable clock
         (&raw mut (*cm_ctrl).gp0_ctl).write_volatile(
             (&raw const (*cm_ctrl).gp0_div).read_volatile() | CM_PASSWORD | CM_ENABLE,
         );
-
-        println!("{:b} {:b}", (&raw mut (*cm_ctrl).gp0_ctl).read_volatile(), (&raw mut (*cm_ctrl).gp0_div).read_volatile())
     }
 }
 
@@ -305,7 +380,11 @@ unsafe fn stop_dma(dma_ctrl: *mut DmaControlRegister) {
     }
 }
 
-unsafe fn start_pwm(pwm_ctrl: *mut PwmControlRegister) {
+/// Resets PWM channel 1 and starts it reading from the FIFO.
+///
+/// `range` is the period in clock cycles (or the number of bits shifted out
+/// per word in serializer mode) and `mode` the channel mode bits OR'd into CTL.
+unsafe fn start_pwm(pwm_ctrl: *mut PwmControlRegister, range: u32, mode: u32) {
     unsafe {
         // Reset PWM
         (&raw mut (*pwm_ctrl).ctl).write_volatile(0);
@@ -313,11 +392,8 @@ unsafe fn start_pwm(pwm_ctrl: *mut PwmControlRegister) {
         (&raw mut (*pwm_ctrl).sta).write_volatile(0);
         sleep(Duration::from_micros(10));
 
-        let target_micros = 100;
-        let cycles = (PLLD_ACTUAL_FREQ / 1_000_000) * target_micros;
-
         // Set range
-        (&raw mut (*pwm_ctrl).rng1).write_volatile(cycles);
+        (&raw mut (*pwm_ctrl).rng1).write_volatile(range);
 
         // Enable PWM DMA and set thresholds for PANIC and DREQ to 15
         (&raw mut (*pwm_ctrl).dmac).write_volatile(PWM_DMA_ENABLE | (15 << 8) | 15);
@@ -328,39 +404,72 @@ unsafe fn start_pwm(pwm_ctrl: *mut PwmControlRegister) {
         sleep(Duration::from_micros(10));
 
         // Enable PWM and use FIFO
-        (&raw mut (*pwm_ctrl).ctl).write_volatile(PWM_USE_FIFO1 | PWM_ENABLE_PWEN1 | PWM_MSEN1);
+        (&raw mut (*pwm_ctrl).ctl).write_volatile(PWM_USE_FIFO1 | PWM_ENABLE_PWEN1 | mode);
+    }
+}
+
+unsafe fn stop_pwm(pwm_ctrl: *mut PwmControlRegister) {
+    unsafe {
+        (&raw mut (*pwm_ctrl).ctl).write_volatile(0);
+        (&raw mut (*pwm_ctrl).dmac).write_volatile(0);
+    }
+}
+
+/// Blocks until the DMA channel has finished its control block chain
+unsafe fn wait_dma(dma_ctrl: *mut DmaControlRegister) {
+    unsafe {
+        while ((&raw const (*dma_ctrl).cs).read_volatile() & DMA_ACTIVE) != 0 {
+            sleep(Duration::from_micros(50));
+        }
+    }
+}
+
+/// Blocks until the DMA channel has loaded the control block at `cb_bus_address`
+unsafe fn wait_for_cb(dma_ctrl: *mut DmaControlRegister, cb_bus_address: u32) {
+    unsafe {
+        while (&raw const (*dma_ctrl).cb_addr).read_volatile() != cb_bus_address {
+            sleep(Duration::from_micros(50));
+        }
     }
 }
 
-pub unsafe fn timer_read_test(num_reads: usize) {
+/// Reads the system timer from DMA `num_reads` times, paced by the PWM clock, and
+/// returns the reads
+pub unsafe fn timer_read_test(num_reads: usize) -> Vec<u32> {
     let num_cbs = num_reads * 2;
 
+    let mut peripherals = DevMem::open().expect("Failed to open peripherals");
+    let board = peripherals.board();
+
     unsafe {
-        let dma_base = map_peripheral(DMA_OFFSET, PAGE_SIZE);
+        let dma_base = peripherals
+            .map_peripheral(DMA_OFFSET, PAGE_SIZE)
+            .expect("Failed to map DMA");
         let mapped_dma_reg: *mut DmaControlRegister =
-            dma_base.offset(DMA_CHANNEL as isize * 0x100).cast();
+            dma_base.offset(board.dma_channel as isize * 0x100).cast();
 
-        let mapped_pwm_reg: *mut PwmControlRegister =
-            map_peripheral(PWM_OFFSET, size_of::<PwmControlRegister>()).cast();
+        let mapped_pwm_reg: *mut PwmControlRegister = peripherals
+            .map_peripheral(PWM_OFFSET, size_of::<PwmControlRegister>())
+            .expect("Failed to map PWM")
+            .cast();
 
-        let mapped_sys_timer_reg: *mut SystemTimerControlRegister =
-            map_peripheral(SYSTEM_TIMER_OFFSET, size_of::<SystemTimerControlRegister>()).cast();
+        let _mapped_sys_timer_reg: *mut SystemTimerControlRegister = peripherals
+            .map_peripheral(SYSTEM_TIMER_OFFSET, size_of::<SystemTimerControlRegister>())
+            .expect("Failed to map system timer")
+            .cast();
 
-            let cm_base = map_peripheral(CM_OFFSET, PAGE_SIZE);
+        let cm_base = peripherals
+            .map_peripheral(CM_OFFSET, PAGE_SIZE)
+            .expect("Failed to map clock manager");
         let mapped_cm_reg: *mut ClockManagerControlRegister =
             cm_base.offset(CM_PWM_CTL_OFFSET as isize).cast();
 
-        sleep(Duration::from_micros(100));
-
-        let mailbox = open_mailbox();
-
-        let dma_cbs = DmaMemoryAllocationHandle::alloc(
-            mailbox,
-            num_cbs * std::mem::size_of::<DmaControlBlock>(),
-        );
-        let dma_ticks =
-            DmaMemoryAllocationHandle::alloc(mailbox, num_reads * std::mem::size_of::<u32>());
-        sleep(Duration::from_micros(100));
+        let dma_cbs = peripherals
+            .alloc_dma_memory(num_cbs * std::mem::size_of::<DmaControlBlock>())
+            .expect("Failed to allocate control blocks");
+        let dma_ticks = peripherals
+            .alloc_dma_memory(num_reads * std::mem::size_of::<u32>())
+            .expect("Failed to allocate tick buffer");
 
         for i in 0..num_reads {
             // Real read operation
@@ -386,7 +495,7 @@ pub unsafe fn timer_read_test(num_reads: usize) {
                 ti: DMA_NO_WIDE_BURSTS | DMA_WAIT_RESP | DMA_DEST_DREQ | (PWM0_DREQ << 16),
                 // system timer address
                 source_ad: nth_cb_bus_address(&dma_cbs, 0) as _,
-                dest_ad: (BUS_PERIPHERAL_BASE + PWM_OFFSET + 0x18) as _,
+                dest_ad: (BUS_PERIPHERAL_BASE + PWM_OFFSET + PWM_FIF1_OFFSET) as _,
                 txfr_len: std::mem::size_of::<u32>() as _,
                 stride: 0,
                 nextconbk: if i < num_reads - 1 {
@@ -398,14 +507,17 @@ pub unsafe fn timer_read_test(num_reads: usize) {
             });
         }
 
-        enable_hardware_timer(mapped_cm_reg);
-        sleep(Duration::from_micros(100));
+        enable_hardware_timer(mapped_cm_reg, PLLD_DIV);
 
-        start_pwm(mapped_pwm_reg);
-        sleep(Duration::from_micros(100));
+        let target_micros = 100;
+        start_pwm(
+            mapped_pwm_reg,
+            (board.plld_freq / PLLD_DIV / 1_000_000) * target_micros,
+            PWM_MSEN1,
+        );
 
         start_dma(mapped_dma_reg, &dma_cbs);
-        sleep(Duration::from_micros(100));
+        wait_dma(mapped_dma_reg);
 
         let mut results = vec![0u32; num_reads];
         results.copy_from_slice(std::slice::from_raw_parts(
@@ -413,8 +525,468 @@ pub unsafe fn timer_read_test(num_reads: usize) {
             num_reads,
         ));
 
-        println!("{:#?}", results);
-
         stop_dma(mapped_dma_reg);
+
+        results
+    }
+}
+
+/// Packs WS2812 symbols MSB-first into 32 bit PWM serializer words
+struct SymbolWriter<'a> {
+    words: &'a mut [u32],
+    bit: usize,
+}
+impl SymbolWriter<'_> {
+    fn push(&mut self, symbol: u32) {
+        for i in (0..WS2812_SYMBOL_BITS).rev() {
+            if (symbol >> i) & 1 != 0 {
+                self.words[self.bit / 32] |= 1 << (31 - self.bit % 32);
+            }
+            self.bit += 1;
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        for i in (0..8).rev() {
+            self.push(if (byte >> i) & 1 != 0 {
+                WS2812_SYMBOL_ONE
+            } else {
+                WS2812_SYMBOL_ZERO
+            });
+        }
+    }
+}
+
+/// Actual serializer bit rate on `board`, which is a little off from
+/// [`WS2812_PWM_FREQ`] since the clock manager divisor is an integer
+#[cfg(test)]
+pub fn ws2812_serializer_freq(board: &Board) -> f64 {
+    board.plld_freq as f64 / (board.plld_freq / WS2812_PWM_FREQ) as f64
+}
+
+/// Number of serializer words needed for a frame of `num_leds`, including the reset
+const fn ws2812_frame_words(num_leds: usize) -> usize {
+    (num_leds * WS2812_BITS_PER_LED).div_ceil(32) + WS2812_RESET_WORDS
+}
+
+/// Most LEDs that fit in a frame of `words`, next to the reset
+const fn ws2812_max_leds(words: usize) -> usize {
+    (words - WS2812_RESET_WORDS) * 32 / WS2812_BITS_PER_LED
+}
+
+/// Encodes `colors` (GRB on the wire) into `words`, which are zeroed first so the
+/// tail of the buffer holds the reset period. Returns the number of LEDs written.
+fn encode_ws2812_frame(
+    words: &mut [u32],
+    colors: impl IntoIterator<Item = RGB8>,
+) -> Result<usize, DmaPwmError> {
+    let max_leds = ws2812_max_leds(words.len());
+
+    words.fill(0);
+    let mut writer = SymbolWriter { words, bit: 0 };
+
+    let mut num_leds = 0;
+    for color in colors {
+        if num_leds == max_leds {
+            return Err(DmaPwmError::TooManyLeds { max: max_leds });
+        }
+
+        writer.push_byte(color.g);
+        writer.push_byte(color.r);
+        writer.push_byte(color.b);
+        num_leds += 1;
+    }
+
+    Ok(num_leds)
+}
+
+#[derive(Debug)]
+pub enum DmaPwmError {
+    /// The frame has more LEDs than the DMA buffer was allocated for
+    TooManyLeds { max: usize },
+    Board(BoardError),
+    Mailbox(MailboxError),
+    /// Mapping the peripherals through `/dev/mem` failed
+    Io(io::Error),
+}
+impl fmt::Display for DmaPwmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DmaPwmError::TooManyLeds { max } => {
+                write!(f, "frame has more LEDs than the {max} DMA/PWM was opened for")
+            }
+            DmaPwmError::Board(err) => write!(f, "{err}"),
+            DmaPwmError::Mailbox(err) => write!(f, "{err}"),
+            DmaPwmError::Io(err) => write!(f, "failed to map the peripherals: {err}"),
+        }
+    }
+}
+impl std::error::Error for DmaPwmError {}
+impl From<BoardError> for DmaPwmError {
+    fn from(err: BoardError) -> Self {
+        DmaPwmError::Board(err)
+    }
+}
+impl From<MailboxError> for DmaPwmError {
+    fn from(err: MailboxError) -> Self {
+        DmaPwmError::Mailbox(err)
+    }
+}
+impl From<io::Error> for DmaPwmError {
+    fn from(err: io::Error) -> Self {
+        DmaPwmError::Io(err)
+    }
+}
+
+/// WS2812 driver on GPIO 18 that shifts frames out of the PWM serializer using DMA,
+/// so LED timing doesn't depend on the CPU or the SPI driver.
+///
+/// The DMA channel runs continuously over two frame buffers, each with its own control
+/// block. The front buffer's control block links to itself, so the current frame is
+/// repeated until a new one is ready. A write fills the back buffer and then points
+/// the front control block at the back one. The DMA engine reads a control block's link
+/// when it loads the block, so it finishes the frame it is sending, sends the front
+/// frame once more with the new link loaded, and only then follows it. A frame is on
+/// the strip one to two frame times after it's written. The channel never has to be
+/// stopped and a frame is never modified while it is being sent.
+pub struct DmaPwmWs2812<P: Peripherals = DevMem> {
+    /// Keeps the register mappings alive for as long as the driver is
+    _peripherals: P,
+
+    dma_reg: *mut DmaControlRegister,
+    pwm_reg: *mut PwmControlRegister,
+
+    /// One control block per frame buffer
+    dma_cbs: DmaMemoryAllocationHandle,
+    /// Both frame buffers, back to back
+    frames: DmaMemoryAllocationHandle,
+    words_per_frame: usize,
+    /// Index of the frame buffer the DMA channel is looping over
+    front: usize,
+
+    /// CPU side copy of the frame, encoded before being copied into DMA memory
+    scratch: Vec<u32>,
+}
+// The mapped registers and DMA memory are only ever touched through `&mut self`
+unsafe impl<P: Peripherals + Send> Send for DmaPwmWs2812<P> {}
+
+impl DmaPwmWs2812 {
+    /// Opens the real peripherals of the detected board, see
+    /// [`DmaPwmWs2812::with_peripherals`].
+    ///
+    /// # Safety
+    /// Pokes `/dev/mem` directly. Nothing else (including audio, which also uses
+    /// PWM0) may use PWM0, GPIO 18 or the board's [`Board::dma_channel`].
+    pub unsafe fn new(max_leds: usize) -> Result<Self, DmaPwmError> {
+        unsafe { Self::with_peripherals(DevMem::open()?, max_leds) }
+    }
+}
+
+impl<P: Peripherals> DmaPwmWs2812<P> {
+    /// Maps the DMA, PWM, clock and GPIO peripherals, allocates enough DMA memory
+    /// for frames of up to `max_leds` and starts continuously sending an all-off frame.
+    ///
+    /// # Safety
+    /// The memory handed out by `peripherals` is written to as registers and DMA
+    /// control blocks.
+    pub unsafe fn with_peripherals(
+        mut peripherals: P,
+        max_leds: usize,
+    ) -> Result<Self, DmaPwmError> {
+        let words_per_frame = ws2812_frame_words(max_leds);
+        let board = peripherals.board();
+
+        unsafe {
+            let dma_base = peripherals.map_peripheral(DMA_OFFSET, PAGE_SIZE)?;
+            let dma_reg: *mut DmaControlRegister =
+                dma_base.offset(board.dma_channel as isize * 0x100).cast();
+
+            let pwm_reg: *mut PwmControlRegister = peripherals
+                .map_peripheral(PWM_OFFSET, size_of::<PwmControlRegister>())?
+                .cast();
+
+            let cm_base = peripherals.map_peripheral(CM_OFFSET, PAGE_SIZE)?;
+            let cm_reg: *mut ClockManagerControlRegister =
+                cm_base.offset(CM_PWM_CTL_OFFSET as isize).cast();
+
+            let gpio_base: *mut u32 = peripherals.map_peripheral(GPIO_OFFSET, PAGE_SIZE)?.cast();
+            set_gpio_function(gpio_base, PWM0_GPIO, GPIO_FSEL_ALT5);
+
+            let dma_cbs = peripherals.alloc_dma_memory(2 * size_of::<DmaControlBlock>())?;
+            let frames = peripherals.alloc_dma_memory(2 * words_per_frame * size_of::<u32>())?;
+
+            // Start with both frames off
+            std::ptr::write_bytes(frames.virtual_memory_address, 0, frames.size);
+
+            for i in 0..2 {
+                nth_cb_virtual_address(&dma_cbs, i).write_volatile(DmaControlBlock {
+                    ti: DMA_NO_WIDE_BURSTS
+                        | DMA_WAIT_RESP
+                        | DMA_SRC_INC
+                        | DMA_DEST_DREQ
+                        | (PWM0_DREQ << 16),
+                    source_ad: frames
+                        .bus_memory_address
+                        .add(i * words_per_frame * size_of::<u32>())
+                        as _,
+                    dest_ad: (BUS_PERIPHERAL_BASE + PWM_OFFSET + PWM_FIF1_OFFSET) as _,
+                    txfr_len: (words_per_frame * size_of::<u32>()) as _,
+                    stride: 0,
+                    // Each frame loops on itself until it's swapped out
+                    nextconbk: nth_cb_bus_address(&dma_cbs, i) as _,
+                    padding: [0, 0],
+                });
+            }
+
+            enable_hardware_timer(cm_reg, board.plld_freq / WS2812_PWM_FREQ);
+            start_pwm(pwm_reg, 32, PWM_MODE1_ENABLE_SERIALIZER);
+            start_dma(dma_reg, &dma_cbs);
+
+            Ok(Self {
+                _peripherals: peripherals,
+
+                dma_reg,
+                pwm_reg,
+
+                dma_cbs,
+                frames,
+                words_per_frame,
+                front: 0,
+
+                scratch: vec![0; words_per_frame],
+            })
+        }
+    }
+
+    #[cfg(test)]
+    pub fn peripherals(&self) -> &P {
+        &self._peripherals
+    }
+
+    /// Most LEDs a frame can have, at least the `max_leds` it was opened with
+    pub fn max_leds(&self) -> usize {
+        ws2812_max_leds(self.words_per_frame)
+    }
+
+    /// Waits until the hardware is looping over the front buffer, after which the
+    /// back buffer is no longer being read and can be rewritten.
+    unsafe fn wait_for_front(&self) {
+        unsafe {
+            wait_for_cb(
+                self.dma_reg,
+                nth_cb_bus_address(&self.dma_cbs, self.front) as _,
+            )
+        };
+    }
+
+    /// Swaps the back buffer in by linking the front control block to it. The hardware
+    /// loaded the front control block with its old link, so it only follows the new one
+    /// after sending the front frame once more, see [`DmaPwmWs2812::wait_for_front`].
+    unsafe fn swap(&mut self) {
+        let back = 1 - self.front;
+
+        unsafe {
+            // The back frame must loop on itself once the hardware gets there
+            (&raw mut (*nth_cb_virtual_address(&self.dma_cbs, back)).nextconbk)
+                .write_volatile(nth_cb_bus_address(&self.dma_cbs, back) as _);
+            // A single aligned word write, so the hardware sees either the old or new link
+            (&raw mut (*nth_cb_virtual_address(&self.dma_cbs, self.front)).nextconbk)
+                .write_volatile(nth_cb_bus_address(&self.dma_cbs, back) as _);
+        }
+
+        self.front = back;
+    }
+}
+
+impl<P: Peripherals> SmartLedsWrite for DmaPwmWs2812<P> {
+    type Error = DmaPwmError;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        encode_ws2812_frame(&mut self.scratch, iterator.into_iter().map(Into::into))?;
+
+        unsafe {
+            // The previous swap only takes effect once the hardware has sent the old front
+            // frame again with the new link loaded, so wait for it before touching what is
+            // now the back buffer
+            self.wait_for_front();
+
+            let back = 1 - self.front;
+            std::ptr::copy_nonoverlapping(
+                self.scratch.as_ptr(),
+                self.frames
+                    .virtual_memory_address
+                    .cast::<u32>()
+                    .add(back * self.words_per_frame),
+                self.words_per_frame,
+            );
+
+            self.swap();
+        }
+
+        Ok(())
+    }
+}
+
+impl<P: Peripherals> Drop for DmaPwmWs2812<P> {
+    fn drop(&mut self) {
+        unsafe {
+            stop_dma(self.dma_reg);
+            stop_pwm(self.pwm_reg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NUM_LEDS: usize = 4;
+
+    fn driver() -> DmaPwmWs2812<FakePeripherals> {
+        unsafe { DmaPwmWs2812::with_peripherals(FakePeripherals::new(), NUM_LEDS) }.unwrap()
+    }
+
+    fn control_block(driver: &DmaPwmWs2812<FakePeripherals>, i: usize) -> DmaControlBlock {
+        unsafe { nth_cb_virtual_address(&driver.dma_cbs, i).read_volatile() }
+    }
+
+    fn cb_bus_address(driver: &DmaPwmWs2812<FakePeripherals>, i: usize) -> u32 {
+        unsafe { nth_cb_bus_address(&driver.dma_cbs, i) as u32 }
+    }
+
+    /// Serializer words for `bits`, a string of 0s and 1s, padded to a whole frame
+    fn words(bits: &str) -> Vec<u32> {
+        let mut words = vec![0; ws2812_frame_words(NUM_LEDS)];
+        for (i, bit) in bits.chars().enumerate() {
+            if bit == '1' {
+                words[i / 32] |= 1 << (31 - i % 32);
+            }
+        }
+        words
+    }
+
+    #[test]
+    fn control_blocks_loop_over_their_own_frame() {
+        let driver = driver();
+        let words_per_frame = ws2812_frame_words(NUM_LEDS);
+        assert_eq!(driver.words_per_frame, words_per_frame);
+
+        assert_eq!(
+            cb_bus_address(&driver, 1) - cb_bus_address(&driver, 0),
+            size_of::<DmaControlBlock>() as u32
+        );
+        for i in 0..2 {
+            let bus_address = cb_bus_address(&driver, i);
+            assert_eq!(
+                driver.peripherals().bus_to_virtual(bus_address),
+                Some(unsafe { nth_cb_virtual_address(&driver.dma_cbs, i) }.cast())
+            );
+
+            let cb = control_block(&driver, i);
+            assert_eq!(cb.nextconbk, bus_address);
+            assert_eq!(
+                cb.source_ad,
+                driver.frames.bus_memory_address as u32 + (i * words_per_frame * 4) as u32
+            );
+            assert_eq!(
+                cb.dest_ad,
+                (BUS_PERIPHERAL_BASE + PWM_OFFSET + PWM_FIF1_OFFSET) as u32
+            );
+            assert_eq!(cb.txfr_len, (words_per_frame * 4) as u32);
+            assert_eq!(
+                cb.ti,
+                DMA_NO_WIDE_BURSTS
+                    | DMA_WAIT_RESP
+                    | DMA_SRC_INC
+                    | DMA_DEST_DREQ
+                    | (PWM0_DREQ << 16)
+            );
+        }
+
+        let dma_reg = driver.dma_reg;
+        assert_eq!(
+            unsafe { (&raw const (*dma_reg).cb_addr).read_volatile() },
+            cb_bus_address(&driver, 0)
+        );
+        assert_ne!(
+            unsafe { (&raw const (*dma_reg).cs).read_volatile() } & DMA_ACTIVE,
+            0
+        );
+    }
+
+    #[test]
+    fn frames_are_sent_as_grb_symbols() {
+        let mut driver = driver();
+        driver
+            .write([RGB8::new(0x80, 0x01, 0x00), RGB8::new(0xFF, 0xFF, 0xFF)])
+            .unwrap();
+
+        // The all-off frame is sent once more before the swap lands
+        assert_eq!(unsafe { driver.peripherals().step_dma() }, words(""));
+
+        let zero = "100";
+        let one = "110";
+        let expected = [
+            // Green 0x01, red 0x80, blue 0x00
+            zero.repeat(7) + one,
+            one.to_owned() + &zero.repeat(7),
+            zero.repeat(8),
+            one.repeat(24),
+        ]
+        .concat();
+        assert_eq!(unsafe { driver.peripherals().step_dma() }, words(&expected));
+        // The new front frame loops until the next write
+        assert_eq!(unsafe { driver.peripherals().step_dma() }, words(&expected));
+    }
+
+    #[test]
+    fn write_links_the_front_block_to_the_back_one() {
+        let mut driver = driver();
+        driver.write([RGB8::new(1, 2, 3)]).unwrap();
+
+        assert_eq!(
+            control_block(&driver, 0).nextconbk,
+            cb_bus_address(&driver, 1)
+        );
+        assert_eq!(
+            control_block(&driver, 1).nextconbk,
+            cb_bus_address(&driver, 1)
+        );
+
+        unsafe {
+            driver.peripherals().step_dma();
+            driver.peripherals().step_dma();
+        }
+        let dma_reg = driver.dma_reg;
+        assert_eq!(
+            unsafe { (&raw const (*dma_reg).cb_addr).read_volatile() },
+            cb_bus_address(&driver, 1)
+        );
+
+        // The next write goes back to the first buffer
+        driver.write([RGB8::new(4, 5, 6)]).unwrap();
+        assert_eq!(
+            control_block(&driver, 1).nextconbk,
+            cb_bus_address(&driver, 0)
+        );
+        assert_eq!(
+            control_block(&driver, 0).nextconbk,
+            cb_bus_address(&driver, 0)
+        );
+    }
+
+    #[test]
+    fn frames_over_max_leds_are_rejected() {
+        let mut driver = driver();
+        let result = driver.write([RGB8::default(); NUM_LEDS + 1]);
+        assert!(matches!(
+            result,
+            Err(DmaPwmError::TooManyLeds { max: NUM_LEDS })
+        ));
     }
 }
@@ -0,0 +1,67 @@
+//! Where the DMA/PWM driver gets its register blocks and DMA memory from.
+//!
+//! [`DevMem`] is the real hardware. Tests use `FakePeripherals` instead, see
+//! [`fake`](super::fake).
+
+use std::{io, sync::Arc};
+
+use super::{Board, DmaMemoryAllocationHandle, DmaPwmError, Mailbox, MailboxError};
+
+pub trait Peripherals {
+    /// The board the peripherals belong to
+    fn board(&self) -> Board;
+
+    /// Maps `size` bytes of the peripheral at `offset` from the peripheral base.
+    /// The returned memory is used as the peripheral's register block, until the
+    /// peripherals are dropped.
+    unsafe fn map_peripheral(&mut self, offset: usize, size: usize) -> io::Result<*mut u8>;
+
+    /// Allocates at least `size` bytes of memory with a fixed bus address the DMA
+    /// engine can read from.
+    fn alloc_dma_memory(&mut self, size: usize) -> Result<DmaMemoryAllocationHandle, MailboxError>;
+}
+
+/// The real peripherals, through `/dev/mem` and the VideoCore mailbox
+pub struct DevMem {
+    board: Board,
+    mailbox: Arc<Mailbox>,
+    /// Every mapped peripheral and its size, unmapped on drop
+    mapped: Vec<(*mut u8, usize)>,
+}
+// Only raw pointers to mappings owned by this struct
+unsafe impl Send for DevMem {}
+
+impl DevMem {
+    /// Detects the board and opens the mailbox. Fails on boards whose peripherals
+    /// we don't know how to drive rather than mapping the wrong memory.
+    pub fn open() -> Result<Self, DmaPwmError> {
+        Ok(Self {
+            board: Board::detect()?,
+            mailbox: Arc::new(Mailbox::open()?),
+            mapped: Vec::new(),
+        })
+    }
+}
+impl Peripherals for DevMem {
+    fn board(&self) -> Board {
+        self.board
+    }
+
+    unsafe fn map_peripheral(&mut self, offset: usize, size: usize) -> io::Result<*mut u8> {
+        let memory = unsafe { super::map_peripheral(self.board.peripheral_base, offset, size)? };
+        self.mapped.push((memory, size));
+        Ok(memory)
+    }
+
+    fn alloc_dma_memory(&mut self, size: usize) -> Result<DmaMemoryAllocationHandle, MailboxError> {
+        DmaMemoryAllocationHandle::alloc(&self.mailbox, size)
+    }
+}
+
+impl Drop for DevMem {
+    fn drop(&mut self) {
+        for (memory, size) in self.mapped.drain(..) {
+            unsafe { libc::munmap(memory.cast(), size) };
+        }
+    }
+}
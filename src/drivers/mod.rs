@@ -1,2 +1,13 @@
+pub mod apa102;
+pub mod capture;
+pub mod clockless;
+pub mod color_order;
+pub mod dma_pwm;
+pub mod sk6812;
 pub mod spi;
-// pub mod dma_pwm;
\ No newline at end of file
+pub mod terminal;
+#[cfg(test)]
+pub mod waveform;
+pub mod ws2811;
+
+pub use color_order::ColorOrder;
@@ -0,0 +1,122 @@
+use std::io;
+
+use palette::LinSrgb;
+use smart_leds::{RGB8, SmartLedsWrite};
+
+use super::{ColorOrder, clockless::SymbolTiming, color_order::WhitePosition, spi::SpiBus};
+
+/// Channel order the driver sends, unless [`Sk6812Rgbw::white_position`] moves white
+pub const SK6812_COLOR_ORDER: ColorOrder = ColorOrder::Grbw;
+
+/// SK6812: 0 is 263ns high, 1 is 526ns high, 1.05us per bit
+const SK6812_TIMING: SymbolTiming = SymbolTiming {
+    spi_clock_hz: 3_800_000,
+    symbol_bits: 4,
+    zero: 0b1000,
+    one: 0b1100,
+    reset_micros: 80,
+};
+
+/// How much of a color is moved onto the dedicated white LED
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhiteExtraction {
+    /// Leave the white LED off and mix white from RGB like a normal strip
+    Off,
+    /// Move the part common to all three channels onto the white LED. Same color, less
+    /// current, and whites look like actual white instead of slightly blue.
+    #[default]
+    Subtract,
+    /// Light the white LED with the common part on top of RGB, for brighter whites
+    Add,
+}
+impl WhiteExtraction {
+    /// Splits `color` into the RGB and white parts
+    pub fn extract(self, color: LinSrgb<f64>) -> (LinSrgb<f64>, f64) {
+        let white = color.red.min(color.green).min(color.blue).max(0.0);
+
+        match self {
+            WhiteExtraction::Off => (color, 0.0),
+            WhiteExtraction::Subtract => (
+                LinSrgb::new(color.red - white, color.green - white, color.blue - white),
+                white,
+            ),
+            WhiteExtraction::Add => (color, white),
+        }
+    }
+}
+
+/// SK6812 RGBW strip. Takes the same `RGB8` frames as the other drivers and derives
+/// the white channel with a [`WhiteExtraction`] policy.
+pub struct Sk6812Rgbw<SPI> {
+    spi: SPI,
+    white: WhiteExtraction,
+    white_position: WhitePosition,
+    data: Vec<u8>,
+}
+impl<SPI: embedded_hal::spi::SpiBus> Sk6812Rgbw<SPI> {
+    /// `spi` should run at 3.8MHz
+    pub fn new(spi: SPI, white: WhiteExtraction) -> Self {
+        Self {
+            spi,
+            white,
+            white_position: WhitePosition::default(),
+            data: Vec::new(),
+        }
+    }
+
+    /// Sends the white byte before or after the color channels, see
+    /// [`ColorOrder::white`]
+    pub fn white_position(mut self, white_position: WhitePosition) -> Self {
+        self.white_position = white_position;
+        self
+    }
+}
+impl Sk6812Rgbw<SpiBus> {
+    pub fn open(bus: &str, white: WhiteExtraction) -> io::Result<Self> {
+        Ok(Self::new(
+            SpiBus::open_with_speed(bus, SK6812_TIMING.spi_clock_hz)?,
+            white,
+        ))
+    }
+
+    /// Most LEDs whose frame fits in a transfer of `max_transfer_bytes`
+    pub fn max_leds(max_transfer_bytes: usize) -> usize {
+        SK6812_TIMING.max_leds(4, max_transfer_bytes)
+    }
+}
+
+impl<SPI: embedded_hal::spi::SpiBus> SmartLedsWrite for Sk6812Rgbw<SPI> {
+    type Error = SPI::Error;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        let white = self.white;
+        let white_position = self.white_position;
+        SK6812_TIMING.encode_frame(
+            &mut self.data,
+            iterator.into_iter().flat_map(|color| {
+                let color: RGB8 = color.into();
+                // Frames arrive calibrated, but the LEDs' PWM is linear in the values
+                // they're sent, so the bytes can still be split directly
+                let (rgb, w) = white.extract(LinSrgb::new(
+                    color.r as f64 / 255.0,
+                    color.g as f64 / 255.0,
+                    color.b as f64 / 255.0,
+                ));
+                let to_u8 = |c: f64| (c * 255.0).round().clamp(0.0, 255.0) as u8;
+
+                let [green, red, blue, w] = [rgb.green, rgb.red, rgb.blue, w].map(to_u8);
+                match white_position {
+                    WhitePosition::First => [w, green, red, blue],
+                    WhitePosition::Last => [green, red, blue, w],
+                }
+            }),
+        );
+
+        self.spi.write(&self.data)
+    }
+}
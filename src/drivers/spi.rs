@@ -1,64 +1,135 @@
-use std::io::{self, Read, Write};
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+};
 use ws2812_spi::hosted::Ws2812;
 
+use super::ColorOrder;
+
+/// Channel order `ws2812_spi` and the DMA/PWM driver send
+pub const WS2812_COLOR_ORDER: ColorOrder = ColorOrder::Grb;
+
+/// SPI clock, every WS2812 bit is encoded as 4 SPI bits by `ws2812_spi`
+pub const SPI_CLOCK_HZ: u32 = 3_800_000;
+
+/// SPI0, data out on GPIO 10
+pub const GPIO_10_BUS: &str = "/dev/spidev0.0";
+/// SPI1
+pub const GPIO_18_BUS: &str = "/dev/spidev1.0";
+
+/// The most bytes spidev takes in one transfer, which a whole frame has to fit in
+const SPIDEV_BUFSIZ_PATH: &str = "/sys/module/spidev/parameters/bufsiz";
+const SPIDEV_DEFAULT_BUFSIZ: usize = 4096;
+
+/// `ws2812_spi` sends 4 SPI bytes per color byte, and 140 zero bytes before and after
+/// every frame for the reset
+const WS2812_SPI_BYTES_PER_LED: usize = 12;
+const WS2812_SPI_RESET_BYTES: usize = 2 * 140;
+
+/// Times a failed transfer is retried on a freshly opened bus before it's given up on
+const REOPEN_ATTEMPTS: usize = 2;
+
+/// A transfer that failed even after reopening the bus
+#[derive(Debug)]
+pub struct SpiError(pub io::Error);
+impl fmt::Display for SpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SPI transfer failed: {}", self.0)
+    }
+}
+impl std::error::Error for SpiError {}
+impl embedded_hal::spi::Error for SpiError {
+    fn kind(&self) -> embedded_hal::spi::ErrorKind {
+        embedded_hal::spi::ErrorKind::Other
+    }
+}
+
+/// A spidev bus, which reopens itself when a transfer fails, e.g. after the device was
+/// reset underneath it
 #[derive(Debug)]
 pub struct SpiBus {
     spi: spidev::Spidev,
+    bus: String,
+    speed_hz: u32,
 }
 impl SpiBus {
     pub fn open(bus: &str) -> io::Result<Self> {
+        Self::open_with_speed(bus, SPI_CLOCK_HZ)
+    }
+
+    pub fn open_with_speed(bus: &str, speed_hz: u32) -> io::Result<Self> {
+        Ok(Self {
+            spi: Self::configure(bus, speed_hz)?,
+            bus: bus.to_owned(),
+            speed_hz,
+        })
+    }
+
+    fn configure(bus: &str, speed_hz: u32) -> io::Result<spidev::Spidev> {
         let mut spi = spidev::Spidev::open(bus)?;
         let options = spidev::SpidevOptions::new()
             .bits_per_word(8)
-            .max_speed_hz(3_800_000)
+            .max_speed_hz(speed_hz)
             .mode(spidev::SpiModeFlags::SPI_MODE_0)
             .build();
         spi.configure(&options)?;
 
-        Ok(Self { spi })
+        Ok(spi)
+    }
+
+    /// Runs `transfer`, reopening the bus and running it again if it fails
+    fn retrying(
+        &mut self,
+        mut transfer: impl FnMut(&mut spidev::Spidev) -> io::Result<()>,
+    ) -> Result<(), SpiError> {
+        let mut result = transfer(&mut self.spi);
+        for _ in 0..REOPEN_ATTEMPTS {
+            if result.is_ok() {
+                break;
+            }
+            result = Self::configure(&self.bus, self.speed_hz).and_then(|spi| {
+                self.spi = spi;
+                transfer(&mut self.spi)
+            });
+        }
+
+        result.map_err(SpiError)
     }
 }
 impl embedded_hal::spi::ErrorType for SpiBus {
-    type Error = embedded_hal::spi::ErrorKind;
+    type Error = SpiError;
 }
 
 impl embedded_hal::spi::SpiBus for SpiBus {
     fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
-        self.spi
-            .read_exact(words)
-            .map_err(|_| embedded_hal::spi::ErrorKind::Other)?;
-        Ok(())
+        self.retrying(|spi| spi.read_exact(words))
     }
 
     fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
-        let mut transfer = spidev::SpidevTransfer::write(words);
-        self.spi
-            .transfer(&mut transfer)
-            .map_err(|_| embedded_hal::spi::ErrorKind::Other)?;
+        self.retrying(|spi| {
+            let mut transfer = spidev::SpidevTransfer::write(words);
+            spi.transfer(&mut transfer)
+        })?;
         _ = self.spi.flush();
 
         Ok(())
     }
 
     fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
-        dbg!();
         assert!(read.len() == write.len());
-        let mut transfer = spidev::SpidevTransfer::read_write(write, read);
-        self.spi
-            .transfer(&mut transfer)
-            .map_err(|_| embedded_hal::spi::ErrorKind::Other)?;
-
-        Ok(())
+        self.retrying(|spi| {
+            let mut transfer = spidev::SpidevTransfer::read_write(write, read);
+            spi.transfer(&mut transfer)
+        })
     }
 
     fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
-        dbg!();
         let mut rx_buf = vec![0; words.len()];
 
-        let mut transfer = spidev::SpidevTransfer::read_write(words, &mut rx_buf);
-        self.spi
-            .transfer(&mut transfer)
-            .map_err(|_| embedded_hal::spi::ErrorKind::Other)?;
+        self.retrying(|spi| {
+            let mut transfer = spidev::SpidevTransfer::read_write(words, &mut rx_buf);
+            spi.transfer(&mut transfer)
+        })?;
 
         words.copy_from_slice(&rx_buf);
         Ok(())
@@ -69,11 +140,21 @@ impl embedded_hal::spi::SpiBus for SpiBus {
     }
 }
 
-pub fn gpio_10() -> std::io::Result<Ws2812<SpiBus>> {
-    let dev = SpiBus::open("/dev/spidev0.0")?;
-    Ok(Ws2812::new(dev))
+/// Most bytes a spidev transfer can have, set by the `spidev.bufsiz` kernel parameter
+pub fn max_transfer_bytes() -> usize {
+    fs::read_to_string(SPIDEV_BUFSIZ_PATH)
+        .ok()
+        .and_then(|bufsiz| bufsiz.trim().parse().ok())
+        .unwrap_or(SPIDEV_DEFAULT_BUFSIZ)
 }
-pub fn gpio_18() -> std::io::Result<Ws2812<SpiBus>> {
-    let dev = SpiBus::open("/dev/spidev1.0")?;
+
+/// Most WS2812s whose frame fits in a transfer of `max_transfer_bytes`
+pub fn ws2812_max_leds(max_transfer_bytes: usize) -> usize {
+    max_transfer_bytes.saturating_sub(WS2812_SPI_RESET_BYTES) / WS2812_SPI_BYTES_PER_LED
+}
+
+/// WS2812s on `bus`, e.g. [`GPIO_10_BUS`]
+pub fn ws2812(bus: &str) -> std::io::Result<Ws2812<SpiBus>> {
+    let dev = SpiBus::open(bus)?;
     Ok(Ws2812::new(dev))
 }
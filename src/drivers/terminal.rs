@@ -0,0 +1,372 @@
+//! Drawing outputs in the terminal, to see what the strips are sent over SSH.
+//!
+//! Every output's strip is wrapped in a [`PreviewStrip`], which keeps the latest frame it
+//! was sent for the [`TerminalPreview`]. [`TerminalPreview::draw`] then draws every zone
+//! as a labelled row of truecolor blocks, with a status line below, over the last time it
+//! drew. Zones with more LEDs than fit in the terminal are averaged down to its width.
+//!
+//! When stdout isn't a terminal only the status line is printed. The terminal's width is
+//! read once, and again whenever it's resized.
+
+use std::{
+    fmt::{Display, Write as _},
+    io::{self, Write as _},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use smart_leds::{RGB8, SmartLedsWrite};
+use unicode_width::UnicodeWidthChar;
+
+use super::ColorOrder;
+use crate::{
+    renderer::OutputConfig,
+    strips::Zone,
+    triple_buffer::{Consumer, Producer, triple_buffer},
+};
+
+/// Width used when the terminal's width can't be read
+const DEFAULT_WIDTH: usize = 80;
+
+/// Shortest time between two draws, so a fast render loop doesn't flood the SSH session
+const MIN_DRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Set by SIGWINCH, so the next draw reads the terminal's width again
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_resize(_signal: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Wraps a strip, keeping the frames sent to it for a [`TerminalPreview`]
+pub struct PreviewStrip<S> {
+    strip: S,
+    frames: Producer<Vec<RGB8>>,
+}
+impl<S: SmartLedsWrite<Color = RGB8>> SmartLedsWrite for PreviewStrip<S> {
+    type Error = S::Error;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        let frame = self.frames.back_mut();
+        frame.clear();
+        frame.extend(iterator.into_iter().map(Into::into));
+
+        let result = self.strip.write(frame.iter().copied());
+        self.frames.publish();
+        result
+    }
+}
+
+/// A row of the preview
+struct PreviewZone {
+    label: String,
+    slots: usize,
+}
+
+struct PreviewOutput {
+    zones: Vec<PreviewZone>,
+    /// Frames as the driver got them, in `driver_order`
+    frames: Consumer<Vec<RGB8>>,
+    color_order: ColorOrder,
+    driver_order: ColorOrder,
+}
+
+/// Draws the latest frame of every output wrapped by [`TerminalPreview::strip`], in place
+pub struct TerminalPreview {
+    outputs: Vec<PreviewOutput>,
+    /// Whether stdout is a terminal, so there's anything to draw the LEDs on
+    interactive: bool,
+    /// Columns of the terminal
+    width: usize,
+    /// Lines drawn last time, which the next draw moves back up over
+    drawn_lines: usize,
+    last_draw: Option<Instant>,
+    buffer: String,
+}
+impl TerminalPreview {
+    pub fn new() -> Self {
+        // SAFETY: isatty only looks at the file descriptor
+        let interactive = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+        let width = if interactive {
+            // SAFETY: The handler only stores to an atomic, which is async-signal-safe
+            unsafe {
+                libc::signal(
+                    libc::SIGWINCH,
+                    on_resize as extern "C" fn(libc::c_int) as libc::sighandler_t,
+                )
+            };
+            terminal_width()
+        } else {
+            DEFAULT_WIDTH
+        };
+
+        Self {
+            outputs: Vec::new(),
+            interactive,
+            width,
+            drawn_lines: 0,
+            last_draw: None,
+            buffer: String::new(),
+        }
+    }
+
+    /// Wraps `strip`, which drives the `zones` of the output called `name`, so the preview
+    /// shows what it's sent
+    pub fn strip<S>(
+        &mut self,
+        name: &str,
+        zones: &[Zone],
+        config: &OutputConfig,
+        strip: S,
+    ) -> PreviewStrip<S> {
+        let (producer, consumer) = triple_buffer(Vec::new());
+        self.outputs.push(PreviewOutput {
+            zones: zones
+                .iter()
+                .map(|zone| PreviewZone {
+                    label: format!("{name} {}", zone.name),
+                    slots: zone.leds.len(),
+                })
+                .collect(),
+            frames: consumer,
+            color_order: config.color_order,
+            driver_order: config.driver_order,
+        });
+
+        PreviewStrip {
+            strip,
+            frames: producer,
+        }
+    }
+
+    /// Draws the LEDs with `status` below them, over the last draw. Does nothing if the
+    /// last draw was too recent.
+    pub fn draw(&mut self, status: &str) {
+        let now = Instant::now();
+        if self
+            .last_draw
+            .is_some_and(|last_draw| now.duration_since(last_draw) < MIN_DRAW_INTERVAL)
+        {
+            return;
+        }
+        self.last_draw = Some(now);
+
+        if self.interactive && RESIZED.swap(false, Ordering::Relaxed) {
+            self.width = terminal_width();
+        }
+        let width = self.width;
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+        self.move_to_top(&mut buffer);
+
+        let mut lines = 1;
+        if self.interactive {
+            let label_width = self
+                .outputs
+                .iter()
+                .flat_map(|output| &output.zones)
+                .map(|zone| display_width(&zone.label))
+                .max()
+                .unwrap_or(0)
+                .min(width / 2);
+            let columns = width.saturating_sub(label_width + 1).max(1);
+
+            for output in &mut self.outputs {
+                output.frames.update();
+                let frame: &[RGB8] = output.frames.front_mut();
+
+                let mut start = 0;
+                for zone in &output.zones {
+                    let end = (start + zone.slots).min(frame.len());
+                    let colors = &frame[start.min(end)..end];
+                    start += zone.slots;
+
+                    let label_end = push_truncated(&mut buffer, &zone.label, label_width);
+                    let _ = write!(buffer, "{:1$}", "", label_width - label_end + 1);
+                    draw_row(&mut buffer, colors, columns, |color| {
+                        output.driver_order.remap(color, output.color_order)
+                    });
+                    buffer.push_str("\x1b[0m\x1b[K\n");
+                    lines += 1;
+                }
+            }
+        }
+
+        push_truncated(&mut buffer, status, width);
+        // Clears the rest of the line and anything left below, in case the last draw was
+        // longer
+        buffer.push_str("\x1b[K\x1b[J");
+
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(buffer.as_bytes());
+        let _ = stdout.flush();
+        self.drawn_lines = lines;
+        self.buffer = buffer;
+    }
+
+    /// Prints `message` above the preview, where the next draw won't overwrite it
+    pub fn message(&mut self, message: impl Display) {
+        let mut buffer = String::new();
+        self.move_to_top(&mut buffer);
+        let _ = writeln!(buffer, "{message}\x1b[K\x1b[J");
+
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(buffer.as_bytes());
+        let _ = stdout.flush();
+        self.drawn_lines = 0;
+        // Drawn again right away, so the preview doesn't disappear until the next draw
+        self.last_draw = None;
+    }
+
+    /// Moves the cursor to the start of the first line drawn last time
+    fn move_to_top(&self, buffer: &mut String) {
+        if self.drawn_lines > 1 {
+            let _ = write!(buffer, "\x1b[{}A", self.drawn_lines - 1);
+        }
+        buffer.push('\r');
+    }
+}
+impl Default for TerminalPreview {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Draws `colors` as `columns` blocks at most, averaging neighbouring LEDs if there are
+/// more. `to_rgb` undoes the driver's color order.
+fn draw_row(buffer: &mut String, colors: &[RGB8], columns: usize, to_rgb: impl Fn(RGB8) -> RGB8) {
+    let columns = columns.min(colors.len());
+    let mut last = None;
+    for column in 0..columns {
+        let start = column * colors.len() / columns;
+        let end = ((column + 1) * colors.len() / columns).max(start + 1);
+
+        let mut sum = [0u32; 3];
+        for color in &colors[start..end] {
+            sum[0] += color.r as u32;
+            sum[1] += color.g as u32;
+            sum[2] += color.b as u32;
+        }
+        let [red, green, blue] = sum.map(|channel| (channel / (end - start) as u32) as u8);
+        let color = to_rgb(RGB8::new(red, green, blue));
+        let color = [color.r, color.g, color.b].map(encode_srgb);
+
+        // Only switching colors when they change keeps solid zones cheap to send
+        if last != Some(color) {
+            let [red, green, blue] = color;
+            let _ = write!(buffer, "\x1b[48;2;{red};{green};{blue}m");
+            last = Some(color);
+        }
+        buffer.push(' ');
+    }
+}
+
+/// The sRGB value a terminal has to be sent to show a channel at the linear `value` the
+/// LEDs were sent, so dim colors look as bright as on the strip
+fn encode_srgb(value: u8) -> u8 {
+    let linear = value as f64 / 255.0;
+    let encoded = if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+/// Columns `text` takes up in a terminal
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            skip_escape(&mut chars, |_| {});
+        } else {
+            width += c.width().unwrap_or(0);
+        }
+    }
+    width
+}
+
+/// Pushes as much of `text` as fits in `width` columns, with every escape sequence in it
+/// so its colors still end where they should. The columns it took up.
+fn push_truncated(buffer: &mut String, text: &str, width: usize) -> usize {
+    let mut used = 0;
+    let mut full = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            buffer.push(c);
+            skip_escape(&mut chars, |c| buffer.push(c));
+            continue;
+        }
+
+        // Control characters would move the cursor, and break `move_to_top`
+        let Some(char_width) = c.width() else {
+            continue;
+        };
+        full |= used + char_width > width;
+        if !full {
+            buffer.push(c);
+            used += char_width;
+        }
+    }
+    used
+}
+
+/// Passes the rest of an escape sequence to `escaped`, after its ESC. Only CSI sequences,
+/// like colors, are longer than one character.
+fn skip_escape(chars: &mut std::str::Chars, mut escaped: impl FnMut(char)) {
+    match chars.next() {
+        Some('[') => {
+            escaped('[');
+            for c in chars.by_ref() {
+                escaped(c);
+                if ('\x40'..='\x7e').contains(&c) {
+                    break;
+                }
+            }
+        }
+        Some(c) => escaped(c),
+        None => {}
+    }
+}
+
+/// Columns of the terminal on stdout
+fn terminal_width() -> usize {
+    // SAFETY: winsize is plain old data
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ only writes a winsize to the pointer it's given
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result == 0 && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        DEFAULT_WIDTH
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_is_truncated_by_display_width() {
+        let mut buffer = String::new();
+        assert_eq!(push_truncated(&mut buffer, "\x1b[31m12345\x1b[0m", 3), 3);
+        assert_eq!(buffer, "\x1b[31m123\x1b[0m");
+
+        // Wide characters take two columns, and don't get split
+        buffer.clear();
+        assert_eq!(push_truncated(&mut buffer, "a漢字b", 4), 3);
+        assert_eq!(buffer, "a漢");
+
+        buffer.clear();
+        assert_eq!(push_truncated(&mut buffer, "one\ntwo", 80), 6);
+        assert_eq!(buffer, "onetwo");
+        assert_eq!(display_width("\x1b[1m漢\x1b[0m"), 2);
+    }
+}
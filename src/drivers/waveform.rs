@@ -0,0 +1,320 @@
+//! Offline WS2812 waveform verifier.
+//!
+//! Takes the exact bitstream a driver hands to its hardware (SPI bytes or PWM serializer
+//! words), turns it back into high/low pulse timings at the configured clock, checks them
+//! against the WS2812B datasheet and decodes the colors, so encoding changes can be
+//! checked without a scope. Only built for tests.
+
+use std::convert::Infallible;
+
+use smart_leds::{RGB8, SmartLedsWrite};
+use ws2812_spi::hosted::Ws2812;
+
+use super::{
+    dma_pwm::{DmaPwmWs2812, FakePeripherals, Peripherals, ws2812_serializer_freq},
+    spi::SPI_CLOCK_HZ,
+};
+
+/// Acceptable pulse widths in nanoseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ws2812Timing {
+    /// High time of a 0 bit
+    pub t0h: (f64, f64),
+    /// High time of a 1 bit
+    pub t1h: (f64, f64),
+    /// Low time between bits. Looser than the datasheet's T0L/T1L since the chip only
+    /// measures the high time, the low time just can't grow into a reset.
+    pub tl: (f64, f64),
+    /// Minimum low time that latches a frame
+    pub reset: f64,
+}
+impl Ws2812Timing {
+    /// WS2812B datasheet values, ±150ns on the high times. The reset is the 280us the
+    /// V5 revision needs rather than the 50us of older parts.
+    pub const WS2812B: Self = Self {
+        t0h: (250.0, 550.0),
+        t1h: (650.0, 950.0),
+        tl: (200.0, 5_000.0),
+        reset: 280_000.0,
+    };
+}
+
+/// A bitstream as it appears on the data line, MSB of every byte/word first
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waveform {
+    /// Duration of every bit in nanoseconds
+    pub bit_ns: f64,
+    pub bits: Vec<bool>,
+}
+impl Waveform {
+    pub fn from_spi_bytes(bytes: &[u8], clock_hz: f64) -> Self {
+        Self {
+            bit_ns: 1e9 / clock_hz,
+            bits: bytes
+                .iter()
+                .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 != 0))
+                .collect(),
+        }
+    }
+
+    pub fn from_pwm_words(words: &[u32], clock_hz: f64) -> Self {
+        Self {
+            bit_ns: 1e9 / clock_hz,
+            bits: words
+                .iter()
+                .flat_map(|word| (0..32).rev().map(move |i| (word >> i) & 1 != 0))
+                .collect(),
+        }
+    }
+
+    /// Run-length encodes the bits into `(level, duration in ns)` pulses
+    pub fn pulses(&self) -> Vec<(bool, f64)> {
+        let mut pulses: Vec<(bool, f64)> = Vec::new();
+        for &bit in &self.bits {
+            match pulses.last_mut() {
+                Some((level, duration)) if *level == bit => *duration += self.bit_ns,
+                _ => pulses.push((bit, self.bit_ns)),
+            }
+        }
+        pulses
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// A high pulse that is neither a valid 0 nor a valid 1
+    HighTime { frame: usize, bit: usize, ns: f64 },
+    /// A low pulse between bits that is too short, or long enough that some chips
+    /// may take it as a reset
+    LowTime { frame: usize, bit: usize, ns: f64 },
+    /// The frame didn't end in a bit count divisible by 24
+    PartialLed { frame: usize, bits: usize },
+    /// The stream ended without holding the line low long enough to latch
+    MissingReset { frame: usize, ns: f64 },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WaveformReport {
+    /// Colors of every latched frame, in order
+    pub frames: Vec<Vec<RGB8>>,
+    pub violations: Vec<Violation>,
+}
+impl WaveformReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Turns the bits of a latched frame into colors
+fn finish_frame(bits: &mut Vec<bool>, report: &mut WaveformReport) {
+    let frame = report.frames.len();
+    if !bits.len().is_multiple_of(24) {
+        report.violations.push(Violation::PartialLed {
+            frame,
+            bits: bits.len(),
+        });
+    }
+
+    report.frames.push(
+        bits.as_chunks::<24>()
+            .0
+            .iter()
+            .map(|led| {
+                let byte = |i: usize| {
+                    led[i * 8..i * 8 + 8]
+                        .iter()
+                        .fold(0u8, |byte, &bit| (byte << 1) | bit as u8)
+                };
+                // GRB on the wire
+                RGB8::new(byte(1), byte(0), byte(2))
+            })
+            .collect(),
+    );
+    bits.clear();
+}
+
+/// Decodes `waveform` back into frames of colors, recording every timing violation
+pub fn decode(waveform: &Waveform, timing: &Ws2812Timing) -> WaveformReport {
+    let mut report = WaveformReport::default();
+    let mut bits = Vec::new();
+
+    // The line idles low before the first bit
+    let pulses = waveform.pulses();
+    let mut pulses = pulses
+        .iter()
+        .skip_while(|(level, _)| !level)
+        .copied()
+        .peekable();
+
+    while let Some((_, high_ns)) = pulses.next() {
+        let frame = report.frames.len();
+        let bit = bits.len();
+
+        let is_one = high_ns >= (timing.t0h.1 + timing.t1h.0) / 2.0;
+        let (min, max) = if is_one { timing.t1h } else { timing.t0h };
+        if !(min..=max).contains(&high_ns) {
+            report.violations.push(Violation::HighTime {
+                frame,
+                bit,
+                ns: high_ns,
+            });
+        }
+        bits.push(is_one);
+
+        match pulses.next() {
+            Some((_, low_ns)) if low_ns >= timing.reset => finish_frame(&mut bits, &mut report),
+            Some((_, low_ns)) => {
+                if !(timing.tl.0..=timing.tl.1).contains(&low_ns) {
+                    report.violations.push(Violation::LowTime {
+                        frame,
+                        bit,
+                        ns: low_ns,
+                    });
+                }
+                // The final low of the stream has to double as the reset
+                if pulses.peek().is_none() {
+                    report
+                        .violations
+                        .push(Violation::MissingReset { frame, ns: low_ns });
+                    finish_frame(&mut bits, &mut report);
+                }
+            }
+            None => {
+                report
+                    .violations
+                    .push(Violation::MissingReset { frame, ns: 0.0 });
+                finish_frame(&mut bits, &mut report);
+            }
+        }
+    }
+
+    report
+}
+
+/// SPI bus that records everything written to it
+#[derive(Debug)]
+struct CaptureBus<'a> {
+    bytes: &'a mut Vec<u8>,
+}
+impl embedded_hal::spi::ErrorType for CaptureBus<'_> {
+    type Error = Infallible;
+}
+impl embedded_hal::spi::SpiBus for CaptureBus<'_> {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        words.fill(0);
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.bytes.extend_from_slice(words);
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        read.fill(0);
+        self.write(write)
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.bytes.extend_from_slice(words);
+        words.fill(0);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// The waveform [`super::spi`] sends for `colors`
+pub fn spi_waveform(colors: &[RGB8]) -> Waveform {
+    let mut bytes = Vec::new();
+    Ws2812::new(CaptureBus { bytes: &mut bytes })
+        .write(colors.iter().copied())
+        .unwrap();
+
+    Waveform::from_spi_bytes(&bytes, SPI_CLOCK_HZ as f64)
+}
+
+/// The waveform [`super::dma_pwm::DmaPwmWs2812`] sends for `colors`, captured by running
+/// the driver against [`FakePeripherals`]
+pub fn dma_pwm_waveform(colors: &[RGB8]) -> Waveform {
+    let mut strip = unsafe { DmaPwmWs2812::with_peripherals(FakePeripherals::new(), colors.len()) }
+        .expect("fake peripherals can't fail to allocate");
+    strip.write(colors.iter().copied()).unwrap();
+
+    let peripherals = strip.peripherals();
+    // The first control block still holds the all-off frame the driver started with,
+    // it's linked to the new frame once that finishes sending
+    let words = unsafe {
+        peripherals.step_dma();
+        peripherals.step_dma()
+    };
+
+    Waveform::from_pwm_words(&words, ws2812_serializer_freq(&peripherals.board()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [RGB8; 4] = [
+        RGB8::new(0, 0, 0),
+        RGB8::new(255, 255, 255),
+        RGB8::new(0x12, 0x34, 0x56),
+        RGB8::new(0xA5, 0x5A, 0x0F),
+    ];
+
+    /// Checks every high pulse of `waveform` against the datasheet directly, rather than
+    /// trusting `decode` to classify them
+    fn assert_high_times_in_spec(waveform: &Waveform) {
+        let timing = Ws2812Timing::WS2812B;
+        for (level, ns) in waveform.pulses() {
+            if level {
+                assert!(
+                    (timing.t0h.0..=timing.t0h.1).contains(&ns)
+                        || (timing.t1h.0..=timing.t1h.1).contains(&ns),
+                    "{ns}ns high"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn spi_round_trips() {
+        let waveform = spi_waveform(&COLORS);
+        assert_high_times_in_spec(&waveform);
+
+        let report = decode(&waveform, &Ws2812Timing::WS2812B);
+        assert!(report.is_ok(), "{:?}", report.violations);
+        assert_eq!(report.frames, [COLORS.to_vec()]);
+    }
+
+    #[test]
+    fn dma_pwm_round_trips() {
+        let waveform = dma_pwm_waveform(&COLORS);
+        assert_high_times_in_spec(&waveform);
+
+        let report = decode(&waveform, &Ws2812Timing::WS2812B);
+        assert!(report.is_ok(), "{:?}", report.violations);
+        assert_eq!(report.frames, [COLORS.to_vec()]);
+    }
+
+    #[test]
+    fn wrong_clock_is_reported() {
+        let mut bytes = Vec::new();
+        Ws2812::new(CaptureBus { bytes: &mut bytes })
+            .write(COLORS)
+            .unwrap();
+
+        // Twice the clock halves every pulse, so 0 bits are too short
+        let waveform = Waveform::from_spi_bytes(&bytes, 2.0 * SPI_CLOCK_HZ as f64);
+        let report = decode(&waveform, &Ws2812Timing::WS2812B);
+        assert!(
+            report
+                .violations
+                .iter()
+                .any(|violation| matches!(violation, Violation::HighTime { .. }))
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::io;
+
+use smart_leds::{RGB8, SmartLedsWrite};
+
+use super::{ColorOrder, clockless::SymbolTiming, spi::SpiBus};
+
+pub const WS2811_COLOR_ORDER: ColorOrder = ColorOrder::Rgb;
+
+/// WS2811 in low speed (400kHz) mode: 0 is 625ns high, 1 is 1.25us high, 2.5us per bit
+const WS2811_TIMING: SymbolTiming = SymbolTiming {
+    spi_clock_hz: 3_200_000,
+    symbol_bits: 8,
+    zero: 0b1100_0000,
+    one: 0b1111_0000,
+    reset_micros: 80,
+};
+
+/// WS2811 pixels, which unlike the WS2812 take their data in RGB order
+pub struct Ws2811<SPI> {
+    spi: SPI,
+    data: Vec<u8>,
+}
+impl<SPI: embedded_hal::spi::SpiBus> Ws2811<SPI> {
+    /// `spi` should run at 3.2MHz
+    pub fn new(spi: SPI) -> Self {
+        Self {
+            spi,
+            data: Vec::new(),
+        }
+    }
+}
+impl Ws2811<SpiBus> {
+    pub fn open(bus: &str) -> io::Result<Self> {
+        Ok(Self::new(SpiBus::open_with_speed(
+            bus,
+            WS2811_TIMING.spi_clock_hz,
+        )?))
+    }
+
+    /// Most LEDs whose frame fits in a transfer of `max_transfer_bytes`
+    pub fn max_leds(max_transfer_bytes: usize) -> usize {
+        WS2811_TIMING.max_leds(3, max_transfer_bytes)
+    }
+}
+
+impl<SPI: embedded_hal::spi::SpiBus> SmartLedsWrite for Ws2811<SPI> {
+    type Error = SPI::Error;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        WS2811_TIMING.encode_frame(
+            &mut self.data,
+            iterator.into_iter().flat_map(|color| {
+                let color: RGB8 = color.into();
+                [color.r, color.g, color.b]
+            }),
+        );
+
+        self.spi.write(&self.data)
+    }
+}
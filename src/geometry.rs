@@ -0,0 +1,427 @@
+//! Curves that LED strips are laid along, with LEDs spaced evenly by arc length.
+//!
+//! ```ignore
+//! // A 60 LEDs/m strip around an intake roller
+//! leds(
+//!     circle(Point { x: 0.0, y: 0.1, z: 0.3 }, Plane::Yz, 0.05, 0.0),
+//!     Spacing::Pitch(60.0),
+//! )
+//! ```
+
+use std::f64::consts::{PI, TAU};
+
+use serde::Deserialize;
+use shark::point::Point;
+
+type Vec3 = [f64; 3];
+
+fn to_vec(point: &Point) -> Vec3 {
+    [point.x, point.y, point.z]
+}
+
+fn to_point(v: Vec3) -> Point {
+    Point {
+        x: v[0],
+        y: v[1],
+        z: v[2],
+    }
+}
+
+fn add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: Vec3, s: f64) -> Vec3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn norm(a: Vec3) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    add(a, scale(sub(b, a), t))
+}
+
+/// How LEDs are spaced along a curve
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Spacing {
+    /// This many LEDs, with the first and last on the ends of the curve. Closed curves
+    /// are divided evenly instead, so the ends don't get an LED each.
+    Count(usize),
+    /// LEDs per meter, as printed on the strip. The LEDs sit in the middle of their
+    /// sections, like on a strip cut to the length of the curve.
+    Pitch(f64),
+}
+
+/// A curve parameterized by distance along it
+pub trait Curve: Clone {
+    /// Length in meters
+    fn length(&self) -> f64;
+    /// The point `distance` meters along the curve, clamped to its ends
+    fn point_at(&self, distance: f64) -> Point;
+    /// Whether the curve ends where it starts
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// LEDs along `curve`, in order from its start
+pub fn leds<C: Curve>(curve: C, spacing: Spacing) -> impl Iterator<Item = Point> + Clone {
+    let length = curve.length();
+    let (count, offset, step) = match spacing {
+        Spacing::Count(count) if curve.is_closed() => (count, 0.0, length / count.max(1) as f64),
+        Spacing::Count(count) => (count, 0.0, length / count.saturating_sub(1).max(1) as f64),
+        Spacing::Pitch(per_meter) => (
+            (length * per_meter).round() as usize,
+            0.5 / per_meter,
+            1.0 / per_meter,
+        ),
+    };
+
+    (0..count).map(move |i| curve.point_at(offset + i as f64 * step))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    start: Vec3,
+    end: Vec3,
+}
+impl Line {
+    pub fn new(start: Point, end: Point) -> Self {
+        Self {
+            start: to_vec(&start),
+            end: to_vec(&end),
+        }
+    }
+}
+impl Curve for Line {
+    fn length(&self) -> f64 {
+        norm(sub(self.end, self.start))
+    }
+
+    fn point_at(&self, distance: f64) -> Point {
+        let length = self.length();
+        if length == 0.0 {
+            return to_point(self.start);
+        }
+
+        to_point(lerp(
+            self.start,
+            self.end,
+            (distance / length).clamp(0.0, 1.0),
+        ))
+    }
+}
+
+/// Plane of an [`Arc`], named by its two axes. Angles go from the first axis towards
+/// the second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Plane {
+    Xy,
+    Xz,
+    Yz,
+}
+impl Plane {
+    fn axes(self) -> (Vec3, Vec3) {
+        match self {
+            Plane::Xy => ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            Plane::Xz => ([1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            Plane::Yz => ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+        }
+    }
+}
+
+/// A circular arc, `center + u cos(a) + v sin(a)` for `a` from 0 to `angle`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arc {
+    center: Vec3,
+    /// From the center to the start, `radius` long
+    u: Vec3,
+    /// Perpendicular to `u` in the direction of travel, `radius` long
+    v: Vec3,
+    radius: f64,
+    /// Radians, never negative
+    angle: f64,
+}
+impl Arc {
+    /// Arc around `center` starting at `start_angle` and turning `sweep` radians, negative
+    /// to go clockwise
+    pub fn new(center: Point, plane: Plane, radius: f64, start_angle: f64, sweep: f64) -> Self {
+        let (e1, e2) = plane.axes();
+        let (sin, cos) = start_angle.sin_cos();
+        let direction = sweep.signum();
+
+        Self {
+            center: to_vec(&center),
+            u: scale(add(scale(e1, cos), scale(e2, sin)), radius),
+            v: scale(add(scale(e1, -sin), scale(e2, cos)), radius * direction),
+            radius,
+            angle: sweep.abs(),
+        }
+    }
+}
+impl Curve for Arc {
+    fn length(&self) -> f64 {
+        self.radius * self.angle
+    }
+
+    fn point_at(&self, distance: f64) -> Point {
+        let angle = if self.radius > 0.0 {
+            (distance / self.radius).clamp(0.0, self.angle)
+        } else {
+            0.0
+        };
+        let (sin, cos) = angle.sin_cos();
+
+        to_point(add(
+            self.center,
+            add(scale(self.u, cos), scale(self.v, sin)),
+        ))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.angle >= TAU
+    }
+}
+
+/// Full counterclockwise circle starting at `start_angle`
+pub fn circle(center: Point, plane: Plane, radius: f64, start_angle: f64) -> Arc {
+    Arc::new(center, plane, radius, start_angle, TAU)
+}
+
+/// Samples used to find distances along a [`CubicBezier`]
+const BEZIER_SAMPLES: usize = 128;
+
+/// Cubic Bezier curve from `p0` to `p3`, pulled towards `p1` and `p2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubicBezier {
+    points: [Vec3; 4],
+    /// Distance along the curve at every sample
+    distances: Vec<f64>,
+}
+impl CubicBezier {
+    pub fn new(p0: Point, p1: Point, p2: Point, p3: Point) -> Self {
+        let mut bezier = Self {
+            points: [to_vec(&p0), to_vec(&p1), to_vec(&p2), to_vec(&p3)],
+            distances: Vec::with_capacity(BEZIER_SAMPLES + 1),
+        };
+
+        let mut distance = 0.0;
+        let mut last = bezier.eval(0.0);
+        for i in 0..=BEZIER_SAMPLES {
+            let point = bezier.eval(i as f64 / BEZIER_SAMPLES as f64);
+            distance += norm(sub(point, last));
+            bezier.distances.push(distance);
+            last = point;
+        }
+
+        bezier
+    }
+
+    fn eval(&self, t: f64) -> Vec3 {
+        let [p0, p1, p2, p3] = self.points;
+        let mt = 1.0 - t;
+
+        add(
+            add(scale(p0, mt * mt * mt), scale(p1, 3.0 * mt * mt * t)),
+            add(scale(p2, 3.0 * mt * t * t), scale(p3, t * t * t)),
+        )
+    }
+}
+impl Curve for CubicBezier {
+    fn length(&self) -> f64 {
+        self.distances.last().copied().unwrap_or(0.0)
+    }
+
+    fn point_at(&self, distance: f64) -> Point {
+        let distance = distance.clamp(0.0, self.length());
+
+        // First sample at or past `distance`, interpolated with the one before it
+        let i = self
+            .distances
+            .partition_point(|&d| d < distance)
+            .clamp(1, BEZIER_SAMPLES);
+        let (before, after) = (self.distances[i - 1], self.distances[i]);
+        let fraction = if after > before {
+            (distance - before) / (after - before)
+        } else {
+            0.0
+        };
+
+        to_point(self.eval((i as f64 - 1.0 + fraction) / BEZIER_SAMPLES as f64))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Piece {
+    Line(Line),
+    Arc(Arc),
+}
+impl Piece {
+    fn length(&self) -> f64 {
+        match self {
+            Piece::Line(line) => line.length(),
+            Piece::Arc(arc) => arc.length(),
+        }
+    }
+
+    fn point_at(&self, distance: f64) -> Point {
+        match self {
+            Piece::Line(line) => line.point_at(distance),
+            Piece::Arc(arc) => arc.point_at(distance),
+        }
+    }
+}
+
+/// Straight runs through `points`, with the corners rounded like a strip bent around
+/// them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polyline {
+    pieces: Vec<Piece>,
+}
+impl Polyline {
+    /// `corner_radius` is reduced where the runs next to a corner are too short for it
+    pub fn new(points: impl IntoIterator<Item = Point>, corner_radius: f64) -> Self {
+        let points: Vec<Vec3> = points.into_iter().map(|point| to_vec(&point)).collect();
+        let mut pieces = Vec::new();
+
+        let Some(&first) = points.first() else {
+            return Self { pieces };
+        };
+        let mut current = first;
+
+        for corner in points.windows(3) {
+            let [before, corner, after] = [corner[0], corner[1], corner[2]];
+            let (incoming, outgoing) = (sub(corner, before), sub(after, corner));
+            let (incoming_len, outgoing_len) = (norm(incoming), norm(outgoing));
+            if incoming_len == 0.0 || outgoing_len == 0.0 {
+                // Repeated point, there's no corner to round
+                continue;
+            }
+
+            let d1 = scale(incoming, 1.0 / incoming_len);
+            let d2 = scale(outgoing, 1.0 / outgoing_len);
+            let turn = dot(d1, d2).clamp(-1.0, 1.0).acos();
+
+            // Distance from the corner to where the arc meets each run. Runs are shared
+            // by the corners on both ends, so each corner gets half.
+            let tangent = (corner_radius * (turn / 2.0).tan())
+                .min(incoming_len / 2.0)
+                .min(outgoing_len / 2.0);
+            // A strip folded straight back has no plane to bend in, so it stays sharp
+            let folded = PI - turn < 1e-6;
+            if corner_radius <= 0.0 || turn < 1e-6 || folded || tangent <= 0.0 {
+                pieces.push(Piece::Line(Line {
+                    start: current,
+                    end: corner,
+                }));
+                current = corner;
+                continue;
+            }
+            let radius = tangent / (turn / 2.0).tan();
+
+            let arc_start = sub(corner, scale(d1, tangent));
+            let arc_end = add(corner, scale(d2, tangent));
+
+            // Towards the center of the arc, perpendicular to the incoming run
+            let inward = sub(d2, scale(d1, dot(d1, d2)));
+            let inward = scale(inward, 1.0 / norm(inward));
+            let center = add(arc_start, scale(inward, radius));
+
+            pieces.push(Piece::Line(Line {
+                start: current,
+                end: arc_start,
+            }));
+            pieces.push(Piece::Arc(Arc {
+                center,
+                u: scale(inward, -radius),
+                v: scale(d1, radius),
+                radius,
+                angle: turn,
+            }));
+            current = arc_end;
+        }
+
+        if let Some(&last) = points.last() {
+            pieces.push(Piece::Line(Line {
+                start: current,
+                end: last,
+            }));
+        }
+
+        Self { pieces }
+    }
+}
+impl Curve for Polyline {
+    fn length(&self) -> f64 {
+        self.pieces.iter().map(Piece::length).sum()
+    }
+
+    fn point_at(&self, mut distance: f64) -> Point {
+        for piece in &self.pieces {
+            let length = piece.length();
+            if distance <= length {
+                return piece.point_at(distance);
+            }
+            distance -= length;
+        }
+
+        match self.pieces.last() {
+            Some(piece) => piece.point_at(piece.length()),
+            None => to_point([0.0; 3]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64, z: f64) -> Point {
+        Point { x, y, z }
+    }
+
+    #[test]
+    fn folded_corner_stays_sharp() {
+        let polyline = Polyline::new(
+            [
+                point(0.0, 0.0, 0.0),
+                point(1.0, 0.0, 0.0),
+                point(0.0, 0.0, 0.0),
+            ],
+            0.1,
+        );
+
+        assert_eq!(polyline.length(), 2.0);
+        for led in leds(polyline, Spacing::Count(9)) {
+            assert!(led.x.is_finite() && led.y.is_finite() && led.z.is_finite());
+        }
+    }
+
+    #[test]
+    fn circle_leds_are_spread_evenly() {
+        let leds: Vec<Point> = leds(
+            circle(point(0.0, 0.0, 0.0), Plane::Xy, 1.0, 0.0),
+            Spacing::Count(4),
+        )
+        .collect();
+
+        let expected = [(1.0, 0.0), (0.0, 1.0), (-1.0, 0.0), (0.0, -1.0)];
+        for (led, (x, y)) in leds.iter().zip(expected) {
+            assert!(
+                (led.x - x).abs() < 1e-9 && (led.y - y).abs() < 1e-9,
+                "{led:?}"
+            );
+        }
+    }
+}
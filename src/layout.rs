@@ -0,0 +1,749 @@
+//! LED layouts loaded from a TOML file at startup, so moving a strip on the robot only
+//! means editing `layout.toml` and copying it over with `just upload-layout`.
+//!
+//! ```toml
+//! # Threads shading each frame, or `{ rayon = 2 }` so they steal LEDs from each other
+//! render_backend = { workers = 2 }
+//!
+//! [[output]]
+//! name = "box_tube"
+//! units = "inches"
+//! # "ws2812", "sk6812" (RGBW), "ws2811" or "apa102"
+//! chipset = "ws2812"
+//! # How frames are sent, "spi" or "dma_pwm" (only WS2812s on GPIO 18)
+//! driver = "spi"
+//! # Channel order the strip is wired in, e.g. "grb" or "wrgb", the chipset's by default
+//! color_order = "grb"
+//!
+//! [[output.segment]]
+//! from = [-9.0, 0.0, 0.0]
+//! to = [9.0, 0.0, 0.0]
+//! # Either a number of LEDs, or the strip's LEDs per meter
+//! leds = 128
+//! # pitch = 60.0
+//! # Corners the strip is bent around between `from` and `to`
+//! via = [[9.0, 0.0, 6.0]]
+//! corner_radius = 1.0
+//! # Zone the segment's LEDs belong to, defaults to the output's name
+//! zone = "box_tube"
+//! # Mechanism the segment rides on, see `mechanisms`
+//! mechanism = "elevator"
+//! # Data flows from `to` to `from`
+//! reverse = false
+//! # LEDs cut out of the segment, the rest keep their spacing
+//! skip = [12, 13]
+//! # LEDs that are still on the strip but broken or hidden, they're kept black
+//! dead = [40]
+//! # Pixels without a position before the segment, e.g. for level shifting
+//! dummies = 1
+//!
+//! # A cubic Bezier curve from `from` to `to`, pulled towards the two control points
+//! [[output.segment]]
+//! from = [-9.0, 0.0, 0.0]
+//! to = [9.0, 0.0, 0.0]
+//! controls = [[-9.0, 0.0, 6.0], [9.0, 0.0, 6.0]]
+//! leds = 40
+//!
+//! # An arc around `center` in the "xy", "xz" or "yz" plane, instead of `from` and `to`
+//! [[output.segment]]
+//! center = [0.0, 4.0, 12.0]
+//! plane = "yz"
+//! radius = 2.0
+//! # Degrees from the plane's first axis towards its second
+//! start_angle = 90.0
+//! # Degrees the arc turns, negative to turn the other way. A full circle if unset.
+//! sweep = 180.0
+//! leds = 30
+//! ```
+
+use std::{f64::consts::TAU, fmt, fs, io, path::Path};
+
+use serde::Deserialize;
+use shark::point::Point;
+
+use crate::{
+    calibration::Calibration,
+    drivers::{
+        ColorOrder, apa102::APA102_COLOR_ORDER, sk6812::SK6812_COLOR_ORDER,
+        spi::WS2812_COLOR_ORDER, ws2811::WS2811_COLOR_ORDER,
+    },
+    geometry::{Arc, CubicBezier, Curve, Plane, Polyline, Spacing, circle, leds},
+    mechanisms::Mechanism,
+    power::PowerBudget,
+    renderer::RenderBackend,
+    strips::Zone,
+};
+
+/// Name of the layout file, looked for next to the executable
+pub const LAYOUT_FILE_NAME: &str = "layout.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    #[default]
+    Meters,
+    Centimeters,
+    Millimeters,
+    Inches,
+    Feet,
+}
+impl Unit {
+    /// Name of the unit in layout files
+    pub fn name(self) -> &'static str {
+        match self {
+            Unit::Meters => "meters",
+            Unit::Centimeters => "centimeters",
+            Unit::Millimeters => "millimeters",
+            Unit::Inches => "inches",
+            Unit::Feet => "feet",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Unit::Meters,
+            Unit::Centimeters,
+            Unit::Millimeters,
+            Unit::Inches,
+            Unit::Feet,
+        ]
+        .into_iter()
+        .find(|unit| unit.name() == name)
+    }
+
+    pub fn to_meters(self, value: f64) -> f64 {
+        match self {
+            Unit::Meters => value,
+            Unit::Centimeters => value * 0.01,
+            Unit::Millimeters => value * 0.001,
+            Unit::Inches => value * 0.0254,
+            Unit::Feet => value * 0.3048,
+        }
+    }
+}
+
+/// LED chip on an output, which decides how its frames are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Chipset {
+    #[default]
+    Ws2812,
+    /// RGBW, see [`Sk6812Rgbw`](crate::drivers::sk6812::Sk6812Rgbw)
+    Sk6812,
+    Ws2811,
+    /// Also SK9822s, on a clocked SPI bus
+    Apa102,
+}
+impl Chipset {
+    /// Channel order the chipset's driver sends `RGB8`s in
+    pub fn driver_order(self) -> ColorOrder {
+        match self {
+            Chipset::Ws2812 => WS2812_COLOR_ORDER,
+            Chipset::Sk6812 => SK6812_COLOR_ORDER,
+            Chipset::Ws2811 => WS2811_COLOR_ORDER,
+            Chipset::Apa102 => APA102_COLOR_ORDER,
+        }
+    }
+}
+
+/// What sends an output's frames to its pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputDriver {
+    /// The SPI bus on the pin
+    #[default]
+    Spi,
+    /// DMA through the PWM serializer, so the timing doesn't depend on the CPU. Only on
+    /// GPIO 18, see [`DmaPwmWs2812`](crate::drivers::dma_pwm::DmaPwmWs2812).
+    DmaPwm,
+}
+
+/// A run of LEDs, straight unless it goes `via` some corners, follows Bezier `controls`
+/// or is an arc around a `center`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Segment {
+    /// Start of the strip, unless it's an arc
+    #[serde(default)]
+    pub from: Option<[f64; 3]>,
+    /// End of the strip, unless it's an arc
+    #[serde(default)]
+    pub to: Option<[f64; 3]>,
+    /// Corners between `from` and `to`
+    #[serde(default)]
+    pub via: Vec<[f64; 3]>,
+    /// Radius the strip bends with around the corners, in the segment's units
+    #[serde(default)]
+    pub corner_radius: f64,
+    /// Control points of a cubic Bezier curve between `from` and `to`
+    #[serde(default)]
+    pub controls: Option<[[f64; 3]; 2]>,
+    /// Center of an arc, which replaces `from` and `to`
+    #[serde(default)]
+    pub center: Option<[f64; 3]>,
+    /// Plane the arc lies in
+    #[serde(default)]
+    pub plane: Option<Plane>,
+    /// Radius of the arc, in the segment's units
+    #[serde(default)]
+    pub radius: Option<f64>,
+    /// Degrees from the first axis of the `plane` towards its second where the arc starts
+    #[serde(default)]
+    pub start_angle: f64,
+    /// Degrees the arc turns, negative to turn the other way. A full circle if unset.
+    #[serde(default)]
+    pub sweep: Option<f64>,
+    /// Number of LEDs, including skipped ones. The first and last are on `from` and `to`.
+    #[serde(default)]
+    pub leds: Option<usize>,
+    /// LEDs per meter (regardless of `units`), for a strip cut to the segment's length
+    #[serde(default)]
+    pub pitch: Option<f64>,
+    /// Overrides the output's units
+    #[serde(default)]
+    pub units: Option<Unit>,
+    /// Name of the zone the LEDs belong to, the output's name if unset
+    #[serde(default)]
+    pub zone: Option<String>,
+    /// Name of the mechanism the LEDs move with
+    #[serde(default)]
+    pub mechanism: Option<String>,
+    /// The strip's data runs from `to` to `from`
+    #[serde(default)]
+    pub reverse: bool,
+    /// Indices (from the start of the data direction) of LEDs that aren't there
+    #[serde(default)]
+    pub skip: Vec<usize>,
+    /// Indices of LEDs that are there, but shouldn't light up. They keep their slot on
+    /// the output and are kept black.
+    #[serde(default)]
+    pub dead: Vec<usize>,
+    /// Pixels before the segment that take a slot on the output, but have no position
+    #[serde(default)]
+    pub dummies: usize,
+}
+impl Segment {
+    /// How errors refer to the segment
+    fn label(&self) -> String {
+        match (self.center, self.from) {
+            (Some(center), _) => format!("segment around {center:?}"),
+            (None, Some(from)) => format!("segment from {from:?}"),
+            (None, None) => "segment".to_owned(),
+        }
+    }
+
+    fn spacing(&self) -> Result<Spacing, LayoutError> {
+        match (self.leds, self.pitch) {
+            (Some(leds), None) => Ok(Spacing::Count(leds)),
+            (None, Some(pitch)) if pitch > 0.0 => Ok(Spacing::Pitch(pitch)),
+            (None, Some(_)) => Err(LayoutError::Invalid(format!(
+                "{} has a pitch that isn't positive",
+                self.label()
+            ))),
+            _ => Err(LayoutError::Invalid(format!(
+                "{} needs exactly one of `leds` and `pitch`",
+                self.label()
+            ))),
+        }
+    }
+
+    /// Checks that the segment has what its kind of curve needs, and nothing else
+    fn check_shape(&self) -> Result<(), LayoutError> {
+        let invalid =
+            |reason: &str| Err(LayoutError::Invalid(format!("{} {reason}", self.label())));
+
+        if self.center.is_some() {
+            if self.from.is_some() || self.to.is_some() || !self.via.is_empty() {
+                return invalid("is an arc, so it can't have `from`, `to` or `via`");
+            }
+            if self.controls.is_some() {
+                return invalid("is an arc, so it can't have `controls`");
+            }
+            if self.plane.is_none() || !self.radius.is_some_and(|radius| radius > 0.0) {
+                return invalid("is an arc, so it needs a `plane` and a positive `radius`");
+            }
+            return Ok(());
+        }
+
+        if self.from.is_none() || self.to.is_none() {
+            return invalid("needs `from` and `to`, or a `center`");
+        }
+        if self.controls.is_some() && !self.via.is_empty() {
+            return invalid("can't have both `via` and `controls`");
+        }
+        if self.plane.is_some() || self.radius.is_some() || self.sweep.is_some() {
+            return invalid("has a `plane`, `radius` or `sweep`, but no `center`");
+        }
+        Ok(())
+    }
+
+    /// Checks that every `skip` and `dead` index is one of the segment's LEDs
+    fn check_indices(&self, default_units: Unit) -> Result<(), LayoutError> {
+        let count = leds(self.curve(default_units), self.spacing()?).count();
+        match self.skip.iter().chain(&self.dead).find(|&&i| i >= count) {
+            Some(i) => Err(LayoutError::Invalid(format!(
+                "{} has {count} LEDs, so it has no LED {i} to skip or mark dead",
+                self.label()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// The curve the strip follows in meters, in data order
+    pub fn curve(&self, default_units: Unit) -> SegmentCurve {
+        let units = self.units.unwrap_or(default_units);
+        let to_point = |pos: &[f64; 3]| Point {
+            x: units.to_meters(pos[0]),
+            y: units.to_meters(pos[1]),
+            z: units.to_meters(pos[2]),
+        };
+        // Checked when the layout is parsed
+        let from = self.from.unwrap_or_default();
+        let to = self.to.unwrap_or_default();
+
+        if let Some(center) = &self.center {
+            let plane = self.plane.unwrap_or(Plane::Xy);
+            let radius = units.to_meters(self.radius.unwrap_or(0.0));
+            let start = self.start_angle.to_radians();
+            let sweep = self.sweep.map_or(TAU, f64::to_radians);
+
+            return SegmentCurve::Arc(match (self.sweep, self.reverse) {
+                (None, false) => circle(to_point(center), plane, radius, start),
+                (_, false) => Arc::new(to_point(center), plane, radius, start, sweep),
+                (_, true) => Arc::new(to_point(center), plane, radius, start + sweep, -sweep),
+            });
+        }
+
+        if let Some([c1, c2]) = &self.controls {
+            let mut points = [&from, c1, c2, &to].map(to_point);
+            if self.reverse {
+                points.reverse();
+            }
+            let [p0, p1, p2, p3] = points;
+            return SegmentCurve::Bezier(CubicBezier::new(p0, p1, p2, p3));
+        }
+
+        let mut corners: Vec<Point> = std::iter::once(&from)
+            .chain(&self.via)
+            .chain(std::iter::once(&to))
+            .map(to_point)
+            .collect();
+        if self.reverse {
+            corners.reverse();
+        }
+
+        SegmentCurve::Polyline(Polyline::new(corners, units.to_meters(self.corner_radius)))
+    }
+
+    /// Slots of the segment on the output in data order, with points in meters for the
+    /// LEDs that aren't dead
+    pub fn slots(&self, default_units: Unit) -> impl Iterator<Item = Option<Point>> + Clone + '_ {
+        // Checked when the layout is parsed
+        let spacing = self.spacing().unwrap_or(Spacing::Count(0));
+
+        std::iter::repeat_n(None, self.dummies).chain(
+            leds(self.curve(default_units), spacing)
+                .enumerate()
+                .filter(|(i, _)| !self.skip.contains(i))
+                .map(|(i, point)| (!self.dead.contains(&i)).then_some(point)),
+        )
+    }
+}
+
+/// The curve a [`Segment`] follows
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentCurve {
+    Polyline(Polyline),
+    Bezier(CubicBezier),
+    Arc(Arc),
+}
+impl Curve for SegmentCurve {
+    fn length(&self) -> f64 {
+        match self {
+            SegmentCurve::Polyline(polyline) => polyline.length(),
+            SegmentCurve::Bezier(bezier) => bezier.length(),
+            SegmentCurve::Arc(arc) => arc.length(),
+        }
+    }
+
+    fn point_at(&self, distance: f64) -> Point {
+        match self {
+            SegmentCurve::Polyline(polyline) => polyline.point_at(distance),
+            SegmentCurve::Bezier(bezier) => bezier.point_at(distance),
+            SegmentCurve::Arc(arc) => arc.point_at(distance),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        match self {
+            SegmentCurve::Polyline(polyline) => polyline.is_closed(),
+            SegmentCurve::Bezier(bezier) => bezier.is_closed(),
+            SegmentCurve::Arc(arc) => arc.is_closed(),
+        }
+    }
+}
+
+/// The LEDs on one driver output, in the order the data reaches them
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputLayout {
+    pub name: String,
+    #[serde(default)]
+    pub units: Unit,
+    #[serde(default)]
+    pub chipset: Chipset,
+    /// Channel order the strip is wired in, the chipset's usual order if unset
+    #[serde(default)]
+    pub color_order: Option<ColorOrder>,
+    #[serde(default)]
+    pub driver: OutputDriver,
+    #[serde(rename = "segment", default)]
+    pub segments: Vec<Segment>,
+    /// See [`Calibration`]
+    #[serde(default)]
+    pub calibration: Calibration,
+    /// Whether to dither the output's colors over frames, on by default
+    #[serde(default)]
+    pub dither: Option<bool>,
+    /// See [`PowerBudget`]
+    #[serde(default)]
+    pub power_budget: Option<PowerBudget>,
+}
+impl OutputLayout {
+    /// Zones of the output in data order. Consecutive segments in the same zone and on
+    /// the same mechanism are merged into one.
+    pub fn zones(&self) -> Vec<Zone> {
+        let mut zones: Vec<Zone> = Vec::new();
+        for segment in &self.segments {
+            let name = segment.zone.as_deref().unwrap_or(&self.name);
+            let continues = matches!(
+                zones.last(),
+                Some(zone) if zone.name == name && zone.mechanism == segment.mechanism
+            );
+            if !continues {
+                zones.push(Zone {
+                    name: name.to_owned(),
+                    mechanism: segment.mechanism.clone(),
+                    leds: Vec::new(),
+                    segments: Vec::new(),
+                });
+            }
+
+            let zone = zones
+                .last_mut()
+                .expect("the zone was continued or just pushed");
+            let start = zone.leds.len();
+            zone.leds.extend(segment.slots(self.units));
+            zone.segments.push(start..zone.leds.len());
+        }
+
+        zones
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layout {
+    #[serde(rename = "output", default)]
+    pub outputs: Vec<OutputLayout>,
+    #[serde(rename = "mechanism", default)]
+    pub mechanisms: Vec<Mechanism>,
+    /// How rendering is spread over threads, rgb-2025's default if unset
+    #[serde(default)]
+    pub render_backend: Option<RenderBackend>,
+}
+
+#[derive(Debug)]
+pub enum LayoutError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Io(err) => write!(f, "failed to read layout: {err}"),
+            LayoutError::Parse(err) => write!(f, "invalid layout: {err}"),
+            LayoutError::Invalid(reason) => write!(f, "invalid layout: {reason}"),
+        }
+    }
+}
+impl std::error::Error for LayoutError {}
+
+impl Layout {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LayoutError> {
+        let source = fs::read_to_string(path).map_err(LayoutError::Io)?;
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, LayoutError> {
+        let layout: Self = toml::from_str(source).map_err(LayoutError::Parse)?;
+
+        let mechanism_exists = |name: &str| {
+            layout
+                .mechanisms
+                .iter()
+                .any(|mechanism| mechanism.name == name)
+        };
+
+        let segments = layout.outputs.iter().flat_map(|output| {
+            output
+                .segments
+                .iter()
+                .map(|segment| (segment, output.units))
+        });
+        for (segment, units) in segments {
+            segment.check_shape()?;
+            segment.check_indices(units)?;
+
+            let missing_mechanism = segment
+                .mechanism
+                .as_ref()
+                .filter(|mechanism| !mechanism_exists(mechanism));
+            if let Some(mechanism) = missing_mechanism {
+                return Err(LayoutError::Invalid(format!(
+                    "{} is on mechanism {mechanism:?}, which isn't defined",
+                    segment.label()
+                )));
+            }
+        }
+
+        for output in &layout.outputs {
+            if output.driver == OutputDriver::DmaPwm && output.chipset != Chipset::Ws2812 {
+                return Err(LayoutError::Invalid(format!(
+                    "output {:?} uses dma_pwm, which only drives WS2812s",
+                    output.name
+                )));
+            }
+            let rgbw = output.chipset == Chipset::Sk6812;
+            let rgbw_order = output
+                .color_order
+                .is_some_and(|order| order.white().is_some());
+            if rgbw_order && !rgbw {
+                return Err(LayoutError::Invalid(format!(
+                    "output {:?} has an RGBW color order, but its chipset has no white",
+                    output.name
+                )));
+            }
+
+            let bad_budget = output
+                .power_budget
+                .filter(|budget| !(budget.amps.is_finite() && budget.amps > 0.0));
+            if let Some(budget) = bad_budget {
+                return Err(LayoutError::Invalid(format!(
+                    "output {:?} has a power budget of {}A, it has to be above 0",
+                    output.name, budget.amps
+                )));
+            }
+        }
+
+        for mechanism in &layout.mechanisms {
+            // Walking up from every mechanism has to reach one without a parent without
+            // coming back to one it already passed
+            let mut chain = vec![mechanism];
+            let mut current = mechanism;
+            while let Some(parent) = &current.parent {
+                current = layout
+                    .mechanisms
+                    .iter()
+                    .find(|mechanism| mechanism.name == *parent)
+                    .ok_or_else(|| {
+                        LayoutError::Invalid(format!(
+                            "mechanism {:?} is mounted on {parent:?}, which isn't defined",
+                            current.name
+                        ))
+                    })?;
+
+                if let Some(start) = chain.iter().position(|passed| passed.name == current.name) {
+                    let cycle: Vec<String> = chain[start..]
+                        .iter()
+                        .chain([&current])
+                        .map(|mechanism| format!("{:?}", mechanism.name))
+                        .collect();
+                    return Err(LayoutError::Invalid(format!(
+                        "mechanisms are mounted on each other in a loop: {}",
+                        cycle.join(" -> ")
+                    )));
+                }
+                chain.push(current);
+            }
+        }
+
+        Ok(layout)
+    }
+
+    /// Loads [`LAYOUT_FILE_NAME`] from next to the executable, where `just upload-layout`
+    /// puts it
+    pub fn load_default() -> Result<Self, LayoutError> {
+        let path = std::env::current_exe()
+            .map_err(LayoutError::Io)?
+            .with_file_name(LAYOUT_FILE_NAME);
+
+        Self::load(path)
+    }
+
+    pub fn output(&self, name: &str) -> Option<&OutputLayout> {
+        self.outputs.iter().find(|output| output.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curved_segments() {
+        let layout = Layout::parse(
+            r#"
+            [[output]]
+            name = "intake"
+
+            [[output.segment]]
+            from = [0.0, 0.0, 0.0]
+            to = [1.0, 0.0, 0.0]
+            controls = [[0.0, 1.0, 0.0], [1.0, 1.0, 0.0]]
+            leds = 3
+
+            [[output.segment]]
+            center = [0.0, 0.0, 0.0]
+            plane = "xz"
+            radius = 2.0
+            sweep = 90.0
+            reverse = true
+            leds = 2
+            "#,
+        )
+        .unwrap();
+
+        let slots: Vec<Point> = layout.outputs[0].zones()[0]
+            .leds
+            .iter()
+            .map(|led| led.clone().unwrap())
+            .collect();
+        let expected = [
+            [0.0, 0.0, 0.0],
+            [0.5, 0.75, 0.0],
+            [1.0, 0.0, 0.0],
+            // The arc runs backwards, from 90° back to 0°
+            [0.0, 0.0, 2.0],
+            [2.0, 0.0, 0.0],
+        ];
+        assert_eq!(slots.len(), expected.len());
+        for (slot, [x, y, z]) in slots.iter().zip(expected) {
+            let close = (slot.x - x).abs() < 1e-2 && (slot.y - y).abs() < 1e-2;
+            assert!(close && (slot.z - z).abs() < 1e-2, "{slot:?}");
+        }
+    }
+
+    #[test]
+    fn arcs_need_a_plane_and_radius() {
+        let result = Layout::parse(
+            r#"
+            [[output]]
+            name = "intake"
+
+            [[output.segment]]
+            center = [0.0, 0.0, 0.0]
+            radius = 2.0
+            leds = 2
+            "#,
+        );
+        assert!(matches!(result, Err(LayoutError::Invalid(_))));
+    }
+    #[test]
+    fn along_runs_over_each_segment() {
+        let layout = Layout::parse(
+            r#"
+            [[output]]
+            name = "underglow"
+
+            [[output.segment]]
+            from = [0.0, 0.0, 0.0]
+            to = [1.0, 0.0, 0.0]
+            leds = 4
+            dummies = 1
+            dead = [1]
+
+            [[output.segment]]
+            from = [1.0, 0.0, 0.0]
+            to = [1.0, 0.0, 1.0]
+            leds = 2
+            "#,
+        )
+        .unwrap();
+
+        let zones = layout.outputs[0].zones();
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].segments, [0..5, 5..7]);
+        let along: Vec<f64> = zones[0].along().collect();
+        assert_eq!(along, [0.0, 0.0, 0.0, 0.5, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn skipped_and_dead_leds_have_to_be_on_the_segment() {
+        let layout = |field: &str| {
+            Layout::parse(&format!(
+                r#"
+                [[output]]
+                name = "intake"
+
+                [[output.segment]]
+                from = [0.0, 0.0, 0.0]
+                to = [1.0, 0.0, 0.0]
+                leds = 4
+                {field}
+                "#
+            ))
+        };
+
+        assert!(layout("skip = [3]").is_ok());
+        assert!(layout("dead = [0]").is_ok());
+        assert!(matches!(layout("skip = [4]"), Err(LayoutError::Invalid(_))));
+        assert!(matches!(
+            layout("dead = [1, 7]"),
+            Err(LayoutError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn mounting_loops_are_reported_whole() {
+        let result = Layout::parse(
+            r#"
+            [[mechanism]]
+            name = "elevator"
+            topic = "elevator"
+            kind = "linear"
+            axis = [0.0, 1.0, 0.0]
+            parent = "wrist"
+
+            [[mechanism]]
+            name = "arm"
+            topic = "arm"
+            kind = "rotary"
+            axis = [1.0, 0.0, 0.0]
+            parent = "elevator"
+
+            [[mechanism]]
+            name = "wrist"
+            topic = "wrist"
+            kind = "rotary"
+            axis = [1.0, 0.0, 0.0]
+            parent = "arm"
+            "#,
+        );
+        let Err(LayoutError::Invalid(message)) = result else {
+            panic!("{result:?}");
+        };
+        assert!(
+            message.ends_with(r#""elevator" -> "wrist" -> "arm" -> "elevator""#),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn render_backend_is_optional() {
+        assert_eq!(Layout::parse("").unwrap().render_backend, None);
+        let layout = Layout::parse("render_backend = { rayon = 4 }").unwrap();
+        assert_eq!(layout.render_backend, Some(RenderBackend::Rayon(4)));
+    }
+}
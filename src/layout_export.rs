@@ -0,0 +1,323 @@
+//! Draws layouts as SVG and PNG, so a layout change can be reviewed without deploying it.
+//!
+//! `just export-layout` writes `layout.svg` and `layout.png` with a top, side and front
+//! view. Every zone gets its own color, every LED is labeled with its slot on the output
+//! every [`LABEL_EVERY`] slots, and segments are drawn as lines from a large dot at the
+//! start to an arrow at the end, in the direction the data flows.
+
+use std::{fmt, fmt::Write, fs, io, path::Path};
+
+use resvg::{tiny_skia, usvg};
+
+use crate::strips::Zone;
+
+/// Size of one view in pixels
+const VIEW_SIZE: f64 = 480.0;
+const MARGIN: f64 = 40.0;
+const LEGEND_LINE_HEIGHT: f64 = 18.0;
+
+/// Slots are labeled every this many slots, and at the ends of every segment
+const LABEL_EVERY: usize = 10;
+
+/// An orthographic projection, by the axis (0 = x, 1 = y, 2 = z) drawn horizontally and
+/// vertically
+struct View {
+    name: &'static str,
+    horizontal: (usize, &'static str),
+    vertical: (usize, &'static str),
+}
+
+/// +y is up and +z is the front of the robot
+const VIEWS: [View; 3] = [
+    View {
+        name: "Top",
+        horizontal: (0, "+x"),
+        vertical: (2, "+z (front)"),
+    },
+    View {
+        name: "Side",
+        horizontal: (2, "+z (front)"),
+        vertical: (1, "+y (up)"),
+    },
+    View {
+        name: "Front",
+        horizontal: (0, "+x"),
+        vertical: (1, "+y (up)"),
+    },
+];
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Svg(usvg::Error),
+    Png(String),
+}
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Io(err) => write!(f, "failed to write export: {err}"),
+            ExportError::Svg(err) => write!(f, "failed to read back svg: {err}"),
+            ExportError::Png(err) => write!(f, "failed to encode png: {err}"),
+        }
+    }
+}
+impl std::error::Error for ExportError {}
+
+/// LEDs of each segment in a zone as (slot, position), leaving out dead LEDs and dummy
+/// pixels
+fn segments(zone: &Zone, first_slot: usize) -> Vec<Vec<(usize, [f64; 3])>> {
+    zone.segments
+        .iter()
+        .map(|segment| {
+            segment
+                .clone()
+                .filter_map(|slot| {
+                    let point = zone.leds[slot].as_ref()?;
+                    Some((first_slot + slot, [point.x, point.y, point.z]))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn zone_color(index: usize) -> String {
+    // Golden angle, so neighboring zones get very different hues
+    format!("hsl({:.0}, 75%, 45%)", (index as f64 * 137.5) % 360.0)
+}
+
+/// Draws the zones of every output, given as (output name, zones)
+pub fn svg(outputs: &[(&str, Vec<Zone>)]) -> String {
+    let positions: Vec<[f64; 3]> = outputs
+        .iter()
+        .flat_map(|(_, zones)| zones)
+        .flat_map(|zone| &zone.leds)
+        .flatten()
+        .map(|point| [point.x, point.y, point.z])
+        .collect();
+
+    // One scale for every view, so they can be compared
+    let mut min = [f64::INFINITY; 3];
+    let mut max = [f64::NEG_INFINITY; 3];
+    for pos in &positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(pos[axis]);
+            max[axis] = max[axis].max(pos[axis]);
+        }
+    }
+    let center: [f64; 3] = [0, 1, 2].map(|axis| {
+        if positions.is_empty() {
+            0.0
+        } else {
+            (min[axis] + max[axis]) / 2.0
+        }
+    });
+    let extent = (0..3)
+        .map(|axis| max[axis] - min[axis])
+        .filter(|extent| extent.is_finite())
+        .fold(0.0, f64::max)
+        .max(0.01);
+    let scale = (VIEW_SIZE - 2.0 * MARGIN) / extent;
+
+    let num_zones: usize = outputs.iter().map(|(_, zones)| zones.len()).sum();
+    let width = VIEW_SIZE * VIEWS.len() as f64;
+    let height = VIEW_SIZE + MARGIN + LEGEND_LINE_HEIGHT * (num_zones as f64 + 1.0);
+
+    let mut svg = String::new();
+    // Writing to a String can't fail
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{width}" height="{height}" fill="white"/>"#
+    );
+
+    for (view_index, view) in VIEWS.iter().enumerate() {
+        let offset = view_index as f64 * VIEW_SIZE;
+        let project = |pos: [f64; 3]| {
+            let (h, v) = (view.horizontal.0, view.vertical.0);
+            (
+                offset + VIEW_SIZE / 2.0 + (pos[h] - center[h]) * scale,
+                VIEW_SIZE / 2.0 - (pos[v] - center[v]) * scale,
+            )
+        };
+
+        let _ = writeln!(
+            svg,
+            r##"<rect x="{}" y="0" width="{VIEW_SIZE}" height="{VIEW_SIZE}" fill="none" stroke="#ccc"/>"##,
+            offset
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="20" font-size="16" font-weight="bold">{}</text>"#,
+            offset + 10.0,
+            view.name
+        );
+        let _ = writeln!(
+            svg,
+            r##"<text x="{}" y="{}" font-size="11" fill="#666" text-anchor="end">{} →</text>"##,
+            offset + VIEW_SIZE - 10.0,
+            VIEW_SIZE - 10.0,
+            view.horizontal.1
+        );
+        let _ = writeln!(
+            svg,
+            r##"<text x="{}" y="{}" font-size="11" fill="#666">↑ {}</text>"##,
+            offset + 10.0,
+            40.0,
+            view.vertical.1
+        );
+
+        let mut zone_index = 0;
+        for (_, zones) in outputs {
+            let mut first_slot = 0;
+            for zone in zones {
+                let color = zone_color(zone_index);
+                let marker = format!("arrow-{view_index}-{zone_index}");
+                let _ = writeln!(
+                    svg,
+                    r#"<defs><marker id="{marker}" viewBox="0 0 10 10" refX="5" refY="5" markerWidth="8" markerHeight="8" orient="auto"><path d="M 0 0 L 10 5 L 0 10 z" fill="{color}"/></marker></defs>"#
+                );
+
+                for segment in segments(zone, first_slot) {
+                    let points: Vec<(f64, f64)> =
+                        segment.iter().map(|(_, pos)| project(*pos)).collect();
+
+                    if points.len() > 1 {
+                        let path: String = points
+                            .iter()
+                            .map(|(x, y)| format!("{x:.1},{y:.1}"))
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        let _ = writeln!(
+                            svg,
+                            r#"<polyline points="{path}" fill="none" stroke="{color}" stroke-width="1.5" marker-end="url(#{marker})"/>"#
+                        );
+                    }
+
+                    // Segment starts are drawn larger
+                    for (i, (x, y)) in points.iter().enumerate() {
+                        let radius = if i == 0 { 4.0 } else { 2.0 };
+                        let _ = writeln!(
+                            svg,
+                            r#"<circle cx="{x:.1}" cy="{y:.1}" r="{radius}" fill="{color}"/>"#
+                        );
+                    }
+
+                    for (i, ((slot, _), (x, y))) in segment.iter().zip(&points).enumerate() {
+                        if i == 0 || i == segment.len() - 1 || slot % LABEL_EVERY == 0 {
+                            let _ = writeln!(
+                                svg,
+                                r#"<text x="{:.1}" y="{:.1}" font-size="8" fill="{color}">{slot}</text>"#,
+                                x + 4.0,
+                                y - 4.0
+                            );
+                        }
+                    }
+                }
+
+                first_slot += zone.leds.len();
+                zone_index += 1;
+            }
+        }
+    }
+
+    // Legend
+    let mut y = VIEW_SIZE + MARGIN;
+    let _ = writeln!(
+        svg,
+        r#"<text x="10" y="{y}" font-size="12">Slots are labeled every {LABEL_EVERY} LEDs and at segment ends. Data flows from the large dot to the arrow, scale is {:.0} px/m.</text>"#,
+        scale
+    );
+    let mut zone_index = 0;
+    for (output, zones) in outputs {
+        for zone in zones {
+            y += LEGEND_LINE_HEIGHT;
+            let leds = zone.leds.iter().flatten().count();
+            let _ = writeln!(
+                svg,
+                r#"<rect x="10" y="{}" width="12" height="12" fill="{}"/><text x="28" y="{y}" font-size="12">{output}: {} ({leds} LEDs, {} slots)</text>"#,
+                y - 10.0,
+                zone_color(zone_index),
+                zone.name,
+                zone.leds.len()
+            );
+            zone_index += 1;
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Renders `svg` to a PNG
+pub fn png(svg: &str) -> Result<Vec<u8>, ExportError> {
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+
+    let tree = usvg::Tree::from_str(svg, &options).map_err(ExportError::Svg)?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| ExportError::Png("layout is too large to draw".to_owned()))?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    pixmap
+        .encode_png()
+        .map_err(|err| ExportError::Png(err.to_string()))
+}
+
+/// Writes `layout.svg` and `layout.png` to `dir`
+pub fn export(outputs: &[(&str, Vec<Zone>)], dir: impl AsRef<Path>) -> Result<(), ExportError> {
+    let dir = dir.as_ref();
+    let svg = svg(outputs);
+
+    fs::create_dir_all(dir).map_err(ExportError::Io)?;
+    fs::write(dir.join("layout.svg"), &svg).map_err(ExportError::Io)?;
+    fs::write(dir.join("layout.png"), png(&svg)?).map_err(ExportError::Io)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::Layout;
+
+    #[test]
+    fn segments_are_drawn_separately() {
+        let layout = Layout::parse(
+            r#"
+            [[output]]
+            name = "underglow"
+
+            [[output.segment]]
+            from = [0.0, 0.0, 0.0]
+            to = [1.0, 0.0, 0.0]
+            leds = 3
+
+            [[output.segment]]
+            from = [1.0, 0.0, 0.5]
+            to = [1.0, 0.0, 2.0]
+            leds = 12
+            "#,
+        )
+        .unwrap();
+        let zones = layout.outputs[0].zones();
+        assert_eq!(zones.len(), 1);
+
+        let svg = svg(&[("underglow", zones)]);
+
+        let labels: Vec<&str> = svg
+            .lines()
+            .filter(|line| line.contains(r#"font-size="8""#))
+            .filter_map(|line| line.strip_suffix("</text>")?.rsplit('>').next())
+            .collect();
+        // The ends of both segments and every tenth slot, in each view
+        let expected = ["0", "2", "3", "10", "14"].repeat(VIEWS.len());
+        assert_eq!(labels, expected);
+
+        let arrows = svg.matches("marker-end=").count();
+        assert_eq!(arrows, 2 * VIEWS.len());
+    }
+}
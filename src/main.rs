@@ -1,49 +1,548 @@
 use std::{
+    sync::atomic::{AtomicBool, Ordering},
     thread::sleep,
     time::{Duration, Instant},
 };
 
-use network_tables::NtReactives;
+use drivers::{
+    apa102::{APA102_MAX_BRIGHTNESS, Apa102},
+    capture::CaptureStrip,
+    dma_pwm::DmaPwmWs2812,
+    sk6812::{Sk6812Rgbw, WhiteExtraction},
+    spi,
+    terminal::TerminalPreview,
+    ws2811::Ws2811,
+};
+use layout::{Chipset, OutputDriver};
+use network_tables::{CoralState, MovementState, NtReactives};
 use palette::LinSrgb;
-use shaders::{ShaderExt2, box_shader, boxtube_shader, transition};
+use renderer::{Output, OutputConfig, RenderBackend};
+use scene::Scene;
+use shaders::{ShaderExt2, box_shader, boxtube_shader, coral_state_conveyor, transition};
 use shark::shader::{ShaderExt, primitives::color};
-use shrewnit::Seconds;
+use shrewnit::{Meters, Seconds};
+use smart_leds::{RGB8, SmartLedsWrite};
+use strips::Zone;
 
+#[cfg(feature = "bench")]
+mod bench;
+mod calibration;
+mod dither;
 mod drivers;
+mod geometry;
+mod layout;
+mod layout_export;
+mod mapping;
+mod mechanisms;
 mod network_tables;
+mod power;
 mod renderer;
+mod scene;
 mod shaders;
 mod strips;
+mod triple_buffer;
+mod validation;
+
+#[cfg(feature = "bench")]
+#[global_allocator]
+static ALLOCATOR: bench::CountingAllocator = bench::CountingAllocator;
+
+/// Used unless the layout picks a backend
+const DEFAULT_RENDER_BACKEND: RenderBackend = RenderBackend::Workers(2);
 
 const DESIRED_FPS: f64 = 101.0;
 const SLEEP_DURATION: Duration = Duration::from_millis((1.0 / DESIRED_FPS * 1000.0) as u64);
 
+/// Set by SIGINT and SIGTERM, so the render loops end and drop their renderer
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_stop(signal: libc::c_int) {
+    STOPPING.store(true, Ordering::Relaxed);
+    // A second one kills the process, in case stopping hangs
+    // SAFETY: signal is async-signal-safe
+    unsafe { libc::signal(signal, libc::SIG_DFL) };
+}
+
+/// Makes SIGINT and SIGTERM end the render loops instead of killing the process, so the
+/// strip drivers are dropped and stop their hardware. Otherwise DMA/PWM keeps looping
+/// over memory that's never freed.
+fn stop_on_signals() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: The handler only stores to an atomic, which is async-signal-safe
+        unsafe {
+            libc::signal(
+                signal,
+                on_stop as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+    }
+}
+
+fn stopping() -> bool {
+    STOPPING.load(Ordering::Relaxed)
+}
+
+/// The render backend `layout` picks, or [`DEFAULT_RENDER_BACKEND`]
+fn render_backend(layout: Option<&layout::Layout>) -> RenderBackend {
+    layout
+        .and_then(|layout| layout.render_backend)
+        .unwrap_or(DEFAULT_RENDER_BACKEND)
+}
+
+/// Zones of the box tube and underglow outputs, from `layout` where it has them
+fn output_zones(layout: Option<&layout::Layout>) -> [(&'static str, Vec<Zone>); 2] {
+    let zones = |name: &str, built_in: fn() -> Vec<Zone>| {
+        layout
+            .and_then(|layout| layout.output(name))
+            .map(|output| output.zones())
+            .unwrap_or_else(built_in)
+    };
+
+    [
+        ("box_tube", zones("box_tube", strips::box_tube_zones)),
+        ("underglow", zones("underglow", strips::underglow_zones)),
+    ]
+}
+
+/// Most slots the driver `layout` gives the output called `name` can send in a frame.
+/// DMA/PWM frame buffers are sized to the output, so they have no limit.
+fn driver_max_leds(layout: Option<&layout::Layout>, name: &str) -> Option<usize> {
+    let (chipset, driver) = layout
+        .and_then(|layout| layout.output(name))
+        .map(|output| (output.chipset, output.driver))
+        .unwrap_or_default();
+
+    // Every frame is sent in one transfer, which the kernel limits
+    let max_transfer_bytes = spi::max_transfer_bytes();
+    match (driver, chipset) {
+        (OutputDriver::DmaPwm, _) => None,
+        (OutputDriver::Spi, Chipset::Ws2812) => Some(spi::ws2812_max_leds(max_transfer_bytes)),
+        (OutputDriver::Spi, Chipset::Sk6812) => Some(Sk6812Rgbw::max_leds(max_transfer_bytes)),
+        (OutputDriver::Spi, Chipset::Ws2811) => Some(Ws2811::max_leds(max_transfer_bytes)),
+        (OutputDriver::Spi, Chipset::Apa102) => Some(Apa102::max_leds(max_transfer_bytes)),
+    }
+}
+
+/// Config of the output called `name`, with the limit of its driver, and its color order,
+/// calibration, dithering and power budget from `layout` if it has them
+fn output_config(layout: Option<&layout::Layout>, name: &str) -> OutputConfig {
+    let default = OutputConfig {
+        max_leds: driver_max_leds(layout, name),
+        ..OutputConfig::default()
+    };
+    let Some(output) = layout.and_then(|layout| layout.output(name)) else {
+        return default;
+    };
+
+    OutputConfig {
+        color_order: output.color_order.unwrap_or(output.chipset.driver_order()),
+        driver_order: output.chipset.driver_order(),
+        calibration: output.calibration,
+        dither: output.dither.unwrap_or(default.dither),
+        power_budget: output.power_budget,
+        ..default
+    }
+}
+
+/// The output called `name` on `strip`, also shown on `preview` if there is one
+fn output<S>(
+    layout: Option<&layout::Layout>,
+    name: &str,
+    zones: Vec<Zone>,
+    strip: S,
+    preview: Option<&mut TerminalPreview>,
+) -> Output
+where
+    S: SmartLedsWrite<Color = RGB8> + Send + 'static,
+    S::Error: std::fmt::Debug,
+{
+    let config = output_config(layout, name);
+    match preview {
+        Some(preview) => {
+            let strip = preview.strip(name, &zones, &config, strip);
+            Output::new(name, strip, zones, config)
+        }
+        None => Output::new(name, strip, zones, config),
+    }
+}
+
+/// The strip `opened` gave the output called `name`, exiting if it couldn't be opened
+fn open_or_exit<S, E: std::fmt::Display>(name: &str, opened: Result<S, E>) -> S {
+    opened.unwrap_or_else(|err| {
+        eprintln!("Failed to open {name}: {err}");
+        std::process::exit(1);
+    })
+}
+
+/// Opens the driver `layout` gives the output called `name`, on the SPI `bus` of its pin
+fn open_output(
+    layout: Option<&layout::Layout>,
+    name: &str,
+    zones: Vec<Zone>,
+    bus: &str,
+    preview: Option<&mut TerminalPreview>,
+) -> Output {
+    let (chipset, driver) = layout
+        .and_then(|layout| layout.output(name))
+        .map(|output| (output.chipset, output.driver))
+        .unwrap_or_default();
+
+    match (driver, chipset) {
+        (OutputDriver::DmaPwm, _) => {
+            if bus != spi::GPIO_18_BUS {
+                eprintln!("{name} can only use dma_pwm on GPIO 18");
+                std::process::exit(1);
+            }
+            let num_slots = zones.iter().map(|zone| zone.leds.len()).sum();
+            // SAFETY: GPIO 18 isn't opened as SPI when it's driven by DMA/PWM, and
+            // nothing else on the robot uses PWM0 or the DMA channel
+            let strip = open_or_exit(name, unsafe { DmaPwmWs2812::new(num_slots) });
+            output(layout, name, zones, strip, preview)
+        }
+        (OutputDriver::Spi, Chipset::Ws2812) => {
+            let strip = open_or_exit(name, spi::ws2812(bus));
+            output(layout, name, zones, strip, preview)
+        }
+        (OutputDriver::Spi, Chipset::Sk6812) => {
+            let white_position = output_config(layout, name).color_order.white();
+            let strip = open_or_exit(name, Sk6812Rgbw::open(bus, WhiteExtraction::default()))
+                .white_position(white_position.unwrap_or_default());
+            output(layout, name, zones, strip, preview)
+        }
+        (OutputDriver::Spi, Chipset::Ws2811) => {
+            let strip = open_or_exit(name, Ws2811::open(bus));
+            output(layout, name, zones, strip, preview)
+        }
+        (OutputDriver::Spi, Chipset::Apa102) => {
+            let strip = open_or_exit(name, Apa102::open(bus, APA102_MAX_BRIGHTNESS));
+            output(layout, name, zones, strip, preview)
+        }
+    }
+}
+
+fn open_outputs(
+    layout: Option<&layout::Layout>,
+    mut preview: Option<&mut TerminalPreview>,
+) -> Vec<Output> {
+    let [(box_tube, box_tube_zones), (underglow, underglow_zones)] = output_zones(layout);
+
+    vec![
+        open_output(
+            layout,
+            box_tube,
+            box_tube_zones,
+            spi::GPIO_10_BUS,
+            preview.as_deref_mut(),
+        ),
+        open_output(
+            layout,
+            underglow,
+            underglow_zones,
+            spi::GPIO_18_BUS,
+            preview,
+        ),
+    ]
+}
+
+/// Outputs on [`CaptureStrip`]s instead of hardware, recording every frame to
+/// `<dir>/<output>.frames` if there's a `dir`
+fn capture_outputs(
+    layout: Option<&layout::Layout>,
+    dir: Option<&str>,
+    preview: &mut TerminalPreview,
+) -> Vec<Output> {
+    output_zones(layout)
+        .into_iter()
+        .map(|(name, zones)| {
+            // Only the file needs every frame
+            let strip = CaptureStrip::new().keep_last(1);
+            let strip = match dir {
+                Some(dir) => {
+                    let path = std::path::Path::new(dir).join(format!("{name}.frames"));
+                    strip.record_to(&path).unwrap_or_else(|err| {
+                        eprintln!("Failed to create {}: {err}", path.display());
+                        std::process::exit(1);
+                    })
+                }
+                None => strip,
+            };
+            output(layout, name, zones, strip, Some(&mut *preview))
+        })
+        .collect()
+}
+
+/// The layout `loaded`, or the built-in one if it couldn't be loaded. Either way it's
+/// validated before anything uses it, exiting if the renderer couldn't run it.
+fn checked_layout(loaded: Result<layout::Layout, layout::LayoutError>) -> Option<layout::Layout> {
+    let layout = loaded
+        .inspect_err(|err| eprintln!("Using built-in layout: {err}"))
+        .ok();
+
+    let outputs: Vec<_> = output_zones(layout.as_ref())
+        .into_iter()
+        .map(|(name, zones)| (name, zones, output_config(layout.as_ref(), name)))
+        .collect();
+    let report = validation::validate(&outputs, render_backend(layout.as_ref()).threads());
+    eprint!("{report}");
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+
+    layout
+}
+
+fn exit_with_usage(usage: &str) -> ! {
+    eprintln!("usage: rgb-2025 {usage}");
+    std::process::exit(1);
+}
+
+/// `map <output> [slot]`
+fn map(args: &[String]) {
+    const USAGE: &str = "map <output> [slot]";
+    let Some(output) = args.first() else {
+        exit_with_usage(USAGE);
+    };
+    let single = args
+        .get(1)
+        .map(|slot| slot.parse().unwrap_or_else(|_| exit_with_usage(USAGE)));
+
+    let layout = checked_layout(layout::Layout::load_default());
+    let mut renderer = renderer::Renderer::new(
+        render_backend(layout.as_ref()),
+        open_outputs(layout.as_ref(), None),
+    );
+
+    let Some(num_slots) = renderer.num_slots(output) else {
+        eprintln!("There's no output called {output:?}");
+        std::process::exit(1);
+    };
+    let pattern = match single {
+        Some(slot) => mapping::MappingPattern::Single(slot),
+        None => mapping::MappingPattern::binary(num_slots),
+    };
+    println!("Mapping {num_slots} slots of {output} with {pattern:?}");
+
+    stop_on_signals();
+    let start_instant = Instant::now();
+    while !stopping() {
+        let frame = (start_instant.elapsed().as_secs_f64() / mapping::MAPPING_FRAME_SECONDS)
+            as usize
+            % pattern.frames();
+
+        let result = renderer.render_slots(|name, slot| {
+            if name == output.as_str() {
+                pattern.color(frame, slot)
+            } else {
+                RGB8::default()
+            }
+        });
+        if let Err(err) = result {
+            eprintln!("{err}");
+        }
+        sleep(SLEEP_DURATION);
+    }
+}
+
+/// `map-to-layout <capture> <output> [units]`
+fn map_to_layout(args: &[String]) {
+    const USAGE: &str = "map-to-layout <capture> <output> [units]";
+    let (Some(capture), Some(output)) = (args.first(), args.get(1)) else {
+        exit_with_usage(USAGE);
+    };
+    let units = match args.get(2) {
+        Some(units) => layout::Unit::from_name(units).unwrap_or_else(|| exit_with_usage(USAGE)),
+        None => layout::Unit::default(),
+    };
+
+    let slots = std::fs::read_to_string(capture)
+        .map_err(|err| err.to_string())
+        .and_then(|source| mapping::parse_capture(&source).map_err(|err| err.to_string()));
+    match slots {
+        Ok(slots) => print!("{}", mapping::capture_to_layout(output, units, &slots)),
+        Err(err) => {
+            eprintln!("Failed to read {capture}: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `export-layout [layout file] [output dir]`
+fn export_layout(args: &[String]) {
+    let layout_path = args
+        .first()
+        .map(String::as_str)
+        .unwrap_or(layout::LAYOUT_FILE_NAME);
+    let dir = args.get(1).map(String::as_str).unwrap_or(".");
+
+    let layout = checked_layout(layout::Layout::load(layout_path));
+
+    match layout_export::export(&output_zones(layout.as_ref()), dir) {
+        Ok(()) => println!("Wrote layout.svg and layout.png to {dir}"),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `snapshot <time> [coral state] [movement state]` prints the colors the box tube shader
+/// gives every LED at `time`, without any hardware
+fn snapshot(args: &[String]) {
+    const USAGE: &str =
+        "snapshot <time> [none|held|transit] [driver|auto-align-path|auto-align-pid|aligned]";
+    let Some(time) = args.first().and_then(|time| time.parse().ok()) else {
+        exit_with_usage(USAGE);
+    };
+    let coral_state = match args.get(1) {
+        Some(name) => CoralState::from_name(name).unwrap_or_else(|| exit_with_usage(USAGE)),
+        None => CoralState::None,
+    };
+    let movement_state = match args.get(2) {
+        Some(name) => MovementState::from_name(name).unwrap_or_else(|| exit_with_usage(USAGE)),
+        None => MovementState::Driver,
+    };
+
+    let layout = checked_layout(layout::Layout::load_default());
+    let (outputs, captures): (Vec<_>, Vec<_>) = output_zones(layout.as_ref())
+        .into_iter()
+        .map(|(name, zones)| {
+            let strip = CaptureStrip::new();
+            let captures = (name, strip.captures());
+            // Dithering would make the colors depend on the frames before
+            let config = OutputConfig {
+                dither: false,
+                ..output_config(layout.as_ref(), name)
+            };
+            (Output::new(name, strip, zones, config), captures)
+        })
+        .unzip();
+
+    let mut renderer = renderer::Renderer::new(render_backend(layout.as_ref()), outputs);
+    let shader = boxtube_shader(coral_state, movement_state, [0.0 * Meters, 0.0 * Meters]);
+    if let Err(err) = renderer.render(shader, time) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+
+    for (name, captures) in captures {
+        if !captures.wait_for(1, Duration::from_secs(1)) {
+            eprintln!("{name} wasn't written");
+            std::process::exit(1);
+        }
+        for frame in captures.frames() {
+            let colors: Vec<String> = frame
+                .colors
+                .iter()
+                .map(|color| format!("{:02x}{:02x}{:02x}", color.r, color.g, color.b))
+                .collect();
+            println!(
+                "{name} after {:.3}s: {}",
+                frame.time.as_secs_f64(),
+                colors.join(" ")
+            );
+        }
+    }
+}
+
+/// `bench [frames]`
+#[cfg(feature = "bench")]
+fn bench(args: &[String]) {
+    const USAGE: &str = "bench [frames]";
+    let frames = match args.first() {
+        Some(frames) => frames.parse().unwrap_or_else(|_| exit_with_usage(USAGE)),
+        None => 1000,
+    };
+
+    let layout = checked_layout(layout::Layout::load_default());
+    let outputs = || {
+        output_zones(layout.as_ref())
+            .into_iter()
+            .map(|(name, zones)| {
+                Output::new(
+                    name,
+                    bench::NullStrip,
+                    zones,
+                    output_config(layout.as_ref(), name),
+                )
+            })
+            .collect()
+    };
+
+    bench::run(outputs, render_backend(layout.as_ref()).threads(), frames);
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        #[cfg(feature = "bench")]
+        Some("bench") => return bench(&args[1..]),
+        #[cfg(not(feature = "bench"))]
+        Some("bench") => {
+            eprintln!("rgb-2025 was built without the bench feature");
+            std::process::exit(1);
+        }
+        Some("export-layout") => return export_layout(&args[1..]),
+        Some("map") => return map(&args[1..]),
+        Some("map-to-layout") => return map_to_layout(&args[1..]),
+        Some("snapshot") => return snapshot(&args[1..]),
+        // `headless [capture dir]` runs as usual, just without hardware
+        Some("headless") if args.len() <= 2 => {}
+        Some("headless") => exit_with_usage("headless [capture dir]"),
+        _ => {}
+    }
+
+    let layout = checked_layout(layout::Layout::load_default());
+    let mechanisms = match &layout {
+        Some(layout) => layout.mechanisms.clone(),
+        None => strips::mechanisms(),
+    };
+
     let NtReactives {
         coral_state,
 
         movement_state,
         position_relative_to_align_target,
 
+        mechanism_values,
+
         topics_last_changed,
-    } = network_tables::start_nt_daemon_task();
+    } = network_tables::start_nt_daemon_task(
+        mechanisms
+            .iter()
+            .map(|mechanism| mechanism.topic.clone())
+            .collect(),
+    );
 
     let start_instant = Instant::now();
 
-    let mut underglow_shader = box_shader(Box::new(
+    let mut box_tube_shader = box_shader(Box::new(
         color(LinSrgb::new(0.0, 0.0, 0.0)).extrude().extrude(),
     ))
     .arc();
+    let mut underglow_shader = box_shader(Box::new(color(LinSrgb::new(0.0, 0.0, 0.0)))).arc();
+    // The coral state runs along each side of the underglow, wherever the side is
+    let [_, (_, underglow_zones)] = output_zones(layout.as_ref());
 
-    let boxtube_points = strips::box_tube_to_intake().collect::<Vec<_>>();
+    let mut preview = TerminalPreview::new();
+    let outputs = match args.first().map(String::as_str) {
+        Some("headless") => capture_outputs(
+            layout.as_ref(),
+            args.get(1).map(String::as_str),
+            &mut preview,
+        ),
+        _ => open_outputs(layout.as_ref(), Some(&mut preview)),
+    };
 
-    let pin_10_renderer = renderer::Renderer::new(2, drivers::spi::gpio_10().unwrap());
+    let mut renderer = renderer::Renderer::new(render_backend(layout.as_ref()), outputs);
+    renderer.attach_mechanisms(mechanisms::Mechanisms::new(mechanisms, mechanism_values));
 
-    loop {
+    stop_on_signals();
+    while !stopping() {
         let loop_start = Instant::now();
 
-        underglow_shader = box_shader(Box::new(transition(
-            underglow_shader,
+        let last_changed = *topics_last_changed.read().unwrap();
+        box_tube_shader = box_shader(Box::new(transition(
+            box_tube_shader,
             boxtube_shader(
                 *coral_state.lock().unwrap(),
                 *movement_state.lock().unwrap(),
@@ -51,20 +550,57 @@ fn main() {
             )
             .to_linsrgb(),
             0.4 * Seconds,
-            *topics_last_changed.read().unwrap(),
+            last_changed,
+        )))
+        .arc();
+        underglow_shader = box_shader(Box::new(transition(
+            underglow_shader,
+            coral_state_conveyor(*coral_state.lock().unwrap()),
+            0.4 * Seconds,
+            last_changed,
         )))
         .arc();
 
+        let scene = underglow_zones
+            .iter()
+            .fold(Scene::new(box_tube_shader.clone()), |scene, zone| {
+                scene.zone_along(zone.name.clone(), underglow_shader.clone())
+            });
+
         let time = start_instant.elapsed().as_secs_f64();
 
-        pin_10_renderer.render(underglow_shader.clone(), boxtube_points.clone(), time);
+        if let Err(err) = renderer.render_scene(&scene, time) {
+            preview.message(err);
+        }
+
+        let power: String = renderer
+            .power()
+            .filter(|(_, status)| status.is_limiting())
+            .map(|(name, status)| {
+                format!(
+                    " {name} limited to {:.0}% ({:.1}A of {:.1}A)",
+                    status.scale * 100.0,
+                    status.limited_amps,
+                    status.estimated_amps
+                )
+            })
+            .collect();
+        let failed_writes: String = renderer
+            .output_stats()
+            .filter(|(_, stats)| stats.failed_writes > 0 || !stats.alive)
+            .map(|(name, stats)| {
+                let dead = if stats.alive { "" } else { ", dead" };
+                format!(" {name}: {} failed writes{dead}", stats.failed_writes)
+            })
+            .collect();
 
         let sleep_dur = SLEEP_DURATION.saturating_sub(loop_start.elapsed());
-        print!(
-            "\rLoop Time: {}us Sleeping for {}ms",
+        preview.draw(&format!(
+            "Loop Time: {}us Shading: {}us Sleeping for {}ms{power}{failed_writes}",
             loop_start.elapsed().as_micros(),
+            renderer.shading_time().as_micros(),
             sleep_dur.as_millis()
-        );
+        ));
         sleep(sleep_dur);
     }
 }
@@ -0,0 +1,248 @@
+//! Finding out which slot of an output is where on the robot, for rewired or new strips.
+//!
+//! - `rgb-2025 map <output>` repeats a [`MappingPattern::Binary`] cycle on every slot
+//!   of the output, so a camera watching the robot can [`decode`] every LED's slot.
+//! - `rgb-2025 map <output> <slot>` lights a single slot, for finding it by eye.
+//! - `rgb-2025 map-to-layout <capture> <output> [units]` turns the captured positions
+//!   into an `[[output]]` for `layout.toml`. The capture has a `slot, x, y[, z]` line
+//!   for every LED that was found.
+
+use std::fmt::{self, Write};
+
+use smart_leds::RGB8;
+
+use crate::layout::Unit;
+
+/// How long every frame of a pattern is shown, long enough for a 30fps camera to
+/// catch every frame at least a few times
+pub const MAPPING_FRAME_SECONDS: f64 = 0.25;
+
+/// Dim, so a whole strip on at once doesn't draw much current or blow out the camera
+const MAPPING_ON: RGB8 = RGB8::new(64, 64, 64);
+
+/// Segments in [`capture_to_layout`] bend at most this many typical LED spacings away
+/// from a straight line
+const SEGMENT_TOLERANCE: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingPattern {
+    /// Every slot shows its index in binary over `bits` frames, least significant bit
+    /// first. Before them every slot is on for a frame and then off for a frame, so a
+    /// camera can tell LEDs apart from the background and find its threshold.
+    Binary { bits: u32 },
+    /// Only this slot is on
+    Single(usize),
+}
+impl MappingPattern {
+    /// Binary pattern with enough bits for `num_slots` slots
+    pub fn binary(num_slots: usize) -> Self {
+        MappingPattern::Binary {
+            bits: (usize::BITS - num_slots.saturating_sub(1).leading_zeros()).max(1),
+        }
+    }
+
+    /// Frames before the pattern repeats
+    pub fn frames(self) -> usize {
+        match self {
+            MappingPattern::Binary { bits } => bits as usize + 2,
+            MappingPattern::Single(_) => 1,
+        }
+    }
+
+    pub fn color(self, frame: usize, slot: usize) -> RGB8 {
+        let on = match self {
+            MappingPattern::Binary { .. } => match frame % self.frames() {
+                0 => true,
+                1 => false,
+                bit => (slot >> (bit - 2)) & 1 == 1,
+            },
+            MappingPattern::Single(single) => slot == single,
+        };
+
+        if on { MAPPING_ON } else { RGB8::default() }
+    }
+}
+
+/// The slot of an LED from whether it was on in every frame of a binary cycle, starting
+/// at the frame where everything is on. `None` if the cycle doesn't start with an on and
+/// an off frame, which means the capture is misaligned.
+pub fn decode(observed: &[bool]) -> Option<usize> {
+    match observed {
+        [true, false, bits @ ..] if bits.len() < usize::BITS as usize => Some(
+            bits.iter()
+                .enumerate()
+                .filter(|(_, on)| **on)
+                .map(|(bit, _)| 1 << bit)
+                .sum(),
+        ),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureError {
+    pub line: usize,
+    pub reason: String,
+}
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+impl std::error::Error for CaptureError {}
+
+/// Parses `slot, x, y[, z]` lines into the position of every slot, `None` for slots that
+/// weren't captured. Blank lines and lines starting with `#` are skipped.
+pub fn parse_capture(source: &str) -> Result<Vec<Option<[f64; 3]>>, CaptureError> {
+    let mut slots = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |reason: String| CaptureError {
+            line: i + 1,
+            reason,
+        };
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if !(3..=4).contains(&fields.len()) {
+            return Err(error(format!(
+                "expected `slot, x, y[, z]`, got {} fields",
+                fields.len()
+            )));
+        }
+
+        let slot: usize = fields[0]
+            .parse()
+            .map_err(|_| error(format!("{:?} isn't a slot", fields[0])))?;
+        let mut pos = [0.0; 3];
+        for (coord, field) in pos.iter_mut().zip(&fields[1..]) {
+            *coord = field
+                .parse()
+                .map_err(|_| error(format!("{field:?} isn't a number")))?;
+        }
+
+        if slots.len() <= slot {
+            slots.resize(slot + 1, None);
+        }
+        if slots[slot].replace(pos).is_some() {
+            return Err(error(format!("slot {slot} was captured twice")));
+        }
+    }
+
+    Ok(slots)
+}
+
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Whether `points` are evenly spaced on the line between the first and last
+fn is_straight(points: &[[f64; 3]], tolerance: f64) -> bool {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let steps = (points.len() - 1).max(1) as f64;
+
+    points.iter().enumerate().all(|(i, point)| {
+        let t = i as f64 / steps;
+        let expected = [0, 1, 2].map(|axis| first[axis] + (last[axis] - first[axis]) * t);
+        distance(*point, expected) <= tolerance
+    })
+}
+
+/// Writes an `[[output]]` for `layout.toml` from the position of every slot (in `units`).
+///
+/// Runs of captured slots are split into as few straight segments as fit them. Slots that
+/// weren't captured become `dummies`, so they keep their slot but get no position.
+pub fn capture_to_layout(name: &str, units: Unit, slots: &[Option<[f64; 3]>]) -> String {
+    let mut spacings: Vec<f64> = slots
+        .windows(2)
+        .filter_map(|pair| Some(distance(pair[0]?, pair[1]?)))
+        .collect();
+    spacings.sort_by(f64::total_cmp);
+    let typical_spacing = spacings.get(spacings.len() / 2).copied().unwrap_or(0.0);
+    let tolerance = typical_spacing * SEGMENT_TOLERANCE;
+
+    let mut toml = String::new();
+    // Writing to a String can't fail
+    let _ = writeln!(
+        toml,
+        "[[output]]\nname = {name:?}\nunits = {:?}",
+        units.name()
+    );
+
+    let format_pos = |pos: [f64; 3]| format!("[{:.4}, {:.4}, {:.4}]", pos[0], pos[1], pos[2]);
+
+    let mut dummies = 0;
+    let mut i = 0;
+    while i < slots.len() {
+        let Some(start) = slots[i] else {
+            dummies += 1;
+            i += 1;
+            continue;
+        };
+
+        // Grow the segment for as long as it stays straight
+        let mut points = vec![start];
+        while let Some(Some(next)) = slots.get(i + points.len()) {
+            points.push(*next);
+            if !is_straight(&points, tolerance) {
+                points.pop();
+                break;
+            }
+        }
+
+        let _ = writeln!(toml, "\n[[output.segment]]");
+        if dummies > 0 {
+            let _ = writeln!(toml, "dummies = {dummies}");
+            dummies = 0;
+        }
+        let _ = writeln!(
+            toml,
+            "from = {}\nto = {}\nleds = {}",
+            format_pos(start),
+            format_pos(points[points.len() - 1]),
+            points.len()
+        );
+
+        i += points.len();
+    }
+
+    if dummies > 0 {
+        let _ = writeln!(
+            toml,
+            "\n# The last {dummies} slots weren't captured, they're left off"
+        );
+    }
+
+    toml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether `slot` is on in every frame of a cycle of `pattern`, starting at `start`
+    fn observe(pattern: MappingPattern, slot: usize, start: usize) -> Vec<bool> {
+        (start..start + pattern.frames())
+            .map(|frame| pattern.color(frame, slot) != RGB8::default())
+            .collect()
+    }
+
+    #[test]
+    fn decode_finds_every_slot() {
+        for num_slots in [1, 2, 3, 64, 65, 300] {
+            let pattern = MappingPattern::binary(num_slots);
+            for slot in 0..num_slots {
+                assert_eq!(decode(&observe(pattern, slot, 0)), Some(slot));
+            }
+        }
+    }
+
+    #[test]
+    fn decode_rejects_misaligned_cycles() {
+        let pattern = MappingPattern::binary(300);
+        assert_eq!(decode(&observe(pattern, 5, 1)), None);
+    }
+}
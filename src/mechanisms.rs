@@ -0,0 +1,303 @@
+//! Mechanisms that carry LEDs around the robot, like the elevator carriage or an arm.
+//!
+//! LED positions in layouts are where the LEDs are with every mechanism at 0. While
+//! rendering, LEDs on a mechanism are moved by the mechanism's latest value from
+//! NetworkTables (meters for linear mechanisms, radians for rotary ones), and then by
+//! its parent's, so shaders see where the LEDs actually are.
+//!
+//! ```toml
+//! [[mechanism]]
+//! name = "elevator"
+//! topic = "RGB/Mechanisms/Elevator Height"
+//! kind = "linear"
+//! axis = [0.0, 1.0, 0.0]
+//!
+//! [[mechanism]]
+//! name = "arm"
+//! topic = "RGB/Mechanisms/Arm Angle"
+//! kind = "rotary"
+//! parent = "elevator"
+//! pivot = [0.0, 20.0, 4.0]
+//! units = "inches"
+//! axis = [1.0, 0.0, 0.0]
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use serde::Deserialize;
+use shark::point::Point;
+
+use crate::layout::Unit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MechanismKind {
+    /// Slides along `axis` by its value in meters
+    Linear,
+    /// Turns around `axis` through `pivot` by its value in radians, counterclockwise
+    /// looking down the axis
+    Rotary,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mechanism {
+    pub name: String,
+    /// NetworkTables topic with the mechanism's position
+    pub topic: String,
+    pub kind: MechanismKind,
+    pub axis: [f64; 3],
+    /// Point the axis of a rotary mechanism goes through, with every mechanism at 0
+    #[serde(default)]
+    pub pivot: [f64; 3],
+    /// Units of `pivot`
+    #[serde(default)]
+    pub units: Unit,
+    /// Mechanism this one is mounted on
+    #[serde(default)]
+    pub parent: Option<String>,
+}
+impl Mechanism {
+    pub fn linear(name: &str, topic: &str, axis: [f64; 3]) -> Self {
+        Self {
+            name: name.to_owned(),
+            topic: topic.to_owned(),
+            kind: MechanismKind::Linear,
+            axis,
+            pivot: [0.0; 3],
+            units: Unit::Meters,
+            parent: None,
+        }
+    }
+
+    /// `pivot` is in meters
+    pub fn rotary(name: &str, topic: &str, pivot: [f64; 3], axis: [f64; 3]) -> Self {
+        Self {
+            kind: MechanismKind::Rotary,
+            pivot,
+            ..Self::linear(name, topic, axis)
+        }
+    }
+
+    pub fn mounted_on(mut self, parent: &str) -> Self {
+        self.parent = Some(parent.to_owned());
+        self
+    }
+
+    /// Where the mechanism moves its LEDs at `value`
+    fn transform(&self, value: f64) -> Transform {
+        let length = self.axis.iter().map(|c| c * c).sum::<f64>().sqrt();
+        if length == 0.0 {
+            return Transform::IDENTITY;
+        }
+        let axis = self.axis.map(|c| c / length);
+
+        match self.kind {
+            MechanismKind::Linear => Transform {
+                translation: axis.map(|c| c * value),
+                ..Transform::IDENTITY
+            },
+            MechanismKind::Rotary => {
+                let rotation = rotation(axis, value);
+                let pivot = self.pivot.map(|c| self.units.to_meters(c));
+                let rotated_pivot = mul(&rotation, pivot);
+
+                // Rotate around the origin, then move the pivot back where it was
+                Transform {
+                    rotation,
+                    translation: [0, 1, 2].map(|i| pivot[i] - rotated_pivot[i]),
+                }
+            }
+        }
+    }
+}
+
+/// Rotation matrix for `angle` radians around the unit vector `axis`
+fn rotation(axis: [f64; 3], angle: f64) -> [[f64; 3]; 3] {
+    let [x, y, z] = axis;
+    let (sin, cos) = angle.sin_cos();
+    let t = 1.0 - cos;
+
+    [
+        [t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y],
+        [t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x],
+        [t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos],
+    ]
+}
+
+fn mul(matrix: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+/// A rotation followed by a translation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    rotation: [[f64; 3]; 3],
+    translation: [f64; 3],
+}
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        translation: [0.0; 3],
+    };
+
+    pub fn apply(&self, point: &Point) -> Point {
+        let [x, y, z] = mul(&self.rotation, [point.x, point.y, point.z]);
+        Point {
+            x: x + self.translation[0],
+            y: y + self.translation[1],
+            z: z + self.translation[2],
+        }
+    }
+
+    /// `self` followed by `then`
+    fn then(&self, then: &Transform) -> Transform {
+        let columns = [0, 1, 2].map(|column| {
+            mul(
+                &then.rotation,
+                [0, 1, 2].map(|row| self.rotation[row][column]),
+            )
+        });
+        let translation = mul(&then.rotation, self.translation);
+
+        Transform {
+            rotation: [0, 1, 2].map(|row| columns.map(|column| column[row])),
+            translation: [0, 1, 2].map(|i| translation[i] + then.translation[i]),
+        }
+    }
+}
+
+/// Latest value of every mechanism topic, written by the NetworkTables daemon.
+///
+/// The topics are fixed when it's created, so reading a value while rendering never
+/// waits on the daemon.
+#[derive(Debug, Clone, Default)]
+pub struct MechanismValues {
+    /// Bits of every topic's `f64` value
+    values: Arc<HashMap<String, AtomicU64>>,
+}
+impl MechanismValues {
+    /// Every topic starts at 0
+    pub fn new(topics: &[String]) -> Self {
+        Self {
+            values: Arc::new(
+                topics
+                    .iter()
+                    .map(|topic| (topic.clone(), AtomicU64::new(0.0f64.to_bits())))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Latest value of `topic`, or `None` if it isn't a mechanism topic
+    pub fn get(&self, topic: &str) -> Option<f64> {
+        self.values
+            .get(topic)
+            .map(|value| f64::from_bits(value.load(Ordering::Relaxed)))
+    }
+
+    /// Updates `topic` if it's a mechanism topic
+    pub fn set(&self, topic: &str, value: f64) {
+        if let Some(stored) = self.values.get(topic) {
+            stored.store(value.to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Every mechanism on the robot, with their live values
+pub struct Mechanisms {
+    mechanisms: Vec<Mechanism>,
+    values: MechanismValues,
+}
+impl Mechanisms {
+    pub fn new(mechanisms: Vec<Mechanism>, values: MechanismValues) -> Self {
+        Self { mechanisms, values }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Mechanism> {
+        self.mechanisms
+            .iter()
+            .find(|mechanism| mechanism.name == name)
+    }
+
+    /// Where LEDs on the mechanism called `name` are right now. Mechanisms without a
+    /// value yet are at 0.
+    pub fn transform(&self, name: &str) -> Transform {
+        let mut transform = Transform::IDENTITY;
+        let mut current = self.get(name);
+        // Bounded, in case a layout mounts mechanisms on each other in a loop
+        for _ in 0..self.mechanisms.len() {
+            let Some(mechanism) = current else {
+                break;
+            };
+            let value = self.values.get(&mechanism.topic).unwrap_or(0.0);
+            transform = transform.then(&mechanism.transform(value));
+            current = mechanism
+                .parent
+                .as_deref()
+                .and_then(|parent| self.get(parent));
+        }
+
+        transform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: [f64; 3]) -> bool {
+        (a.x - b[0]).abs() < 1e-9 && (a.y - b[1]).abs() < 1e-9 && (a.z - b[2]).abs() < 1e-9
+    }
+
+    #[test]
+    fn then_applies_self_first() {
+        let slide = Mechanism::linear("slide", "slide", [0.0, 1.0, 0.0]).transform(0.5);
+        let turn = Mechanism::rotary("turn", "turn", [0.0; 3], [1.0, 0.0, 0.0])
+            .transform(std::f64::consts::FRAC_PI_2);
+        let point = Point {
+            x: 0.0,
+            y: 0.0,
+            z: 0.1,
+        };
+
+        let composed = slide.then(&turn).apply(&point);
+        assert!(close(composed, [0.0, -0.1, 0.5]), "{composed:?}");
+        let composed = turn.then(&slide).apply(&point);
+        assert!(close(composed, [0.0, 0.4, 0.0]), "{composed:?}");
+    }
+
+    #[test]
+    fn children_move_before_their_parents() {
+        // An arm 20cm up the elevator, turned a quarter turn up and raised by 0.5m
+        let topics = ["elevator".to_owned(), "arm".to_owned()];
+        let values = MechanismValues::new(&topics);
+        values.set("elevator", 0.5);
+        values.set("arm", std::f64::consts::FRAC_PI_2);
+        let mechanisms = Mechanisms::new(
+            vec![
+                Mechanism::linear("elevator", "elevator", [0.0, 1.0, 0.0]),
+                Mechanism::rotary("arm", "arm", [0.0, 0.2, 0.0], [1.0, 0.0, 0.0])
+                    .mounted_on("elevator"),
+            ],
+            values,
+        );
+
+        // 10cm in front of the pivot
+        let led = Point {
+            x: 0.0,
+            y: 0.2,
+            z: 0.1,
+        };
+        let moved = mechanisms.transform("arm").apply(&led);
+        assert!(close(moved, [0.0, 0.6, 0.0]), "{moved:?}");
+        let moved = mechanisms.transform("elevator").apply(&led);
+        assert!(close(moved, [0.0, 0.7, 0.1]), "{moved:?}");
+    }
+}
@@ -9,6 +9,8 @@ use network_tables::{rmpv::Integer, v4::{Client, Config}};
 use shrewnit::{Length, Meters};
 use smol::Timer;
 
+use crate::mechanisms::MechanismValues;
+
 const CORAL_STATE_TOPIC: &str = "RGB/Coral State";
 const MOVEMENT_STATE_TOPIC: &str = "RGB/Movement State";
 
@@ -22,6 +24,16 @@ pub enum CoralState {
     Held = 1,
     Transit = 2,
 }
+impl CoralState {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(CoralState::None),
+            "held" => Some(CoralState::Held),
+            "transit" => Some(CoralState::Transit),
+            _ => None,
+        }
+    }
+}
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MovementState {
@@ -30,6 +42,17 @@ pub enum MovementState {
     AutoAlignPid = 2,
     SuccessfullyAligned = 3,
 }
+impl MovementState {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "driver" => Some(MovementState::Driver),
+            "auto-align-path" => Some(MovementState::AutoAlignPath),
+            "auto-align-pid" => Some(MovementState::AutoAlignPid),
+            "aligned" => Some(MovementState::SuccessfullyAligned),
+            _ => None,
+        }
+    }
+}
 
 pub async fn setup_nt_client() -> Client {
     loop {
@@ -56,16 +79,22 @@ pub struct NtReactives {
     pub movement_state: Arc<Mutex<MovementState>>,
     pub position_relative_to_align_target: Arc<Mutex<[Length; 2]>>,
 
+    /// By topic, for every topic passed to [`start_nt_daemon_task`]
+    pub mechanism_values: MechanismValues,
+
     pub topics_last_changed: Arc<RwLock<std::time::Instant>>,
 }
 
-pub fn start_nt_daemon_task() -> NtReactives {
+/// `mechanism_topics` are the topics of the mechanisms that carry LEDs
+pub fn start_nt_daemon_task(mechanism_topics: Vec<String>) -> NtReactives {
     // Reactive values
     let coral_state = Arc::new(Mutex::new(CoralState::None));
 
     let movement_state = Arc::new(Mutex::new(MovementState::Driver));
     let position_relative_to_align_target = Arc::new(Mutex::new([0.0 * Meters, 0.0 * Meters]));
 
+    let mechanism_values = MechanismValues::new(&mechanism_topics);
+
     let topics_last_changed = Arc::new(RwLock::new(std::time::Instant::now()));
 
     // Clone the reactive values for the async task
@@ -74,6 +103,8 @@ pub fn start_nt_daemon_task() -> NtReactives {
 
     let movement_state_clone = movement_state.clone();
     let position_relative_to_align_target_clone = position_relative_to_align_target.clone();
+
+    let mechanism_values_clone = mechanism_values.clone();
     std::thread::spawn(move || {
         smol::block_on(Compat::new(async {
             let client = setup_nt_client().await;
@@ -87,6 +118,11 @@ pub fn start_nt_daemon_task() -> NtReactives {
                 .subscribe(&[POSITION_RELATIVE_TO_ALIGN_TARGET_TOPIC])
                 .await
                 .unwrap();
+            let mut mechanism_sub = if mechanism_topics.is_empty() {
+                None
+            } else {
+                Some(client.subscribe(&mechanism_topics).await.unwrap())
+            };
 
             loop {
                 select! {
@@ -122,6 +158,22 @@ pub fn start_nt_daemon_task() -> NtReactives {
                         let mut lock = position_relative_to_align_target_clone.lock().unwrap();
                         *lock = [value[0] * Meters, value[1] * Meters];
                     },
+                    data = async {
+                        match &mut mechanism_sub {
+                            Some(sub) => sub.next().await,
+                            None => futures::future::pending().await,
+                        }
+                    }.fuse() => {
+                        let data = data.unwrap();
+                        // Positions are doubles, but accept ints from code that publishes those
+                        let Some(value) = data.data.as_f64().or_else(|| data.data.as_i64().map(|value| value as f64)) else {
+                            println!("Invalid mechanism position on {}", data.topic_name);
+                            continue
+                        };
+                        mechanism_values_clone.set(&data.topic_name, value);
+                        // Mechanisms move all the time, which isn't a change in what's shown
+                        continue;
+                    },
                 }
 
                 let mut lock = topics_last_changed_clone.write().unwrap();
@@ -136,6 +188,8 @@ pub fn start_nt_daemon_task() -> NtReactives {
         movement_state,
         position_relative_to_align_target,
 
+        mechanism_values,
+
         topics_last_changed,
     }
 }
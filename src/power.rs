@@ -0,0 +1,250 @@
+//! Keeping every output under the current limit of the regulator powering it.
+//!
+//! Every frame, a [`Limiter`] estimates the current an output's colors would draw from
+//! its [`LedModel`]. If that's over the output's [`PowerBudget`], the whole frame is
+//! dimmed to fit. The limiter dims right away, so a full white frame never browns out
+//! the regulator, and brightens again over a fraction of a second, so it doesn't pump
+//! when a shader hovers around the budget.
+//!
+//! ```toml
+//! [output.power_budget]
+//! amps = 4.0
+//! # Defaults to a WS2812
+//! led = { channel_amps = [0.02, 0.02, 0.02], idle_amps = 0.001 }
+//! ```
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use serde::Deserialize;
+
+use crate::dither::{RGB16_MAX, Rgb16};
+
+/// How fast a limiter brightens again once a frame fits in the budget, in scale per
+/// second
+const RECOVERY_PER_SECOND: f64 = 2.0;
+
+/// Current draw of one LED
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LedModel {
+    /// Current of red, green and blue at full brightness, in amps
+    pub channel_amps: [f64; 3],
+    /// Current of the LED's driver chip, even when it's off, in amps
+    pub idle_amps: f64,
+}
+impl LedModel {
+    pub const WS2812: Self = Self {
+        channel_amps: [0.02; 3],
+        idle_amps: 0.001,
+    };
+
+    /// Current of one LED at full white
+    pub fn max_amps(&self) -> f64 {
+        self.idle_amps + self.channel_amps.iter().sum::<f64>()
+    }
+
+    /// Current of one LED showing `color`, on top of `idle_amps`
+    fn color_amps(&self, color: &Rgb16) -> f64 {
+        (0..3)
+            .map(|channel| {
+                self.channel_amps[channel] * color[channel].min(RGB16_MAX) as f64 / RGB16_MAX as f64
+            })
+            .sum()
+    }
+}
+impl Default for LedModel {
+    fn default() -> Self {
+        Self::WS2812
+    }
+}
+
+/// Most current an output may draw
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PowerBudget {
+    pub amps: f64,
+    /// LEDs on the output
+    #[serde(default)]
+    pub led: LedModel,
+}
+
+/// What a [`Limiter`] did to the latest frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterStatus {
+    /// Current the frame would have drawn as shaded
+    pub estimated_amps: f64,
+    /// Current the frame draws after limiting
+    pub limited_amps: f64,
+    /// Brightness the frame was scaled by, 1.0 when it isn't limited
+    pub scale: f64,
+}
+impl LimiterStatus {
+    pub fn is_limiting(&self) -> bool {
+        self.scale < 1.0
+    }
+}
+impl Default for LimiterStatus {
+    fn default() -> Self {
+        Self {
+            estimated_amps: 0.0,
+            limited_amps: 0.0,
+            scale: 1.0,
+        }
+    }
+}
+
+/// The [`LimiterStatus`] of a [`Limiter`], readable from any thread without waiting on
+/// its writer thread. A read can mix two frames' statuses if it races a write.
+#[derive(Debug)]
+pub struct SharedLimiterStatus {
+    /// Bits of the status's `f64`s
+    estimated_amps: AtomicU64,
+    limited_amps: AtomicU64,
+    scale: AtomicU64,
+}
+impl SharedLimiterStatus {
+    fn new(status: LimiterStatus) -> Self {
+        Self {
+            estimated_amps: AtomicU64::new(status.estimated_amps.to_bits()),
+            limited_amps: AtomicU64::new(status.limited_amps.to_bits()),
+            scale: AtomicU64::new(status.scale.to_bits()),
+        }
+    }
+
+    pub fn load(&self) -> LimiterStatus {
+        LimiterStatus {
+            estimated_amps: f64::from_bits(self.estimated_amps.load(Ordering::Relaxed)),
+            limited_amps: f64::from_bits(self.limited_amps.load(Ordering::Relaxed)),
+            scale: f64::from_bits(self.scale.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn store(&self, status: LimiterStatus) {
+        self.estimated_amps
+            .store(status.estimated_amps.to_bits(), Ordering::Relaxed);
+        self.limited_amps
+            .store(status.limited_amps.to_bits(), Ordering::Relaxed);
+        self.scale.store(status.scale.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Scales the frames of one output into its budget
+#[derive(Debug)]
+pub struct Limiter {
+    budget: Option<PowerBudget>,
+    /// Lowest a channel that's on is dimmed to, see [`Limiter::min_brightness`]
+    min_brightness: u16,
+    scale: f64,
+    last_frame: Option<Instant>,
+    status: Arc<SharedLimiterStatus>,
+}
+impl Limiter {
+    /// A limiter for `budget`, which doesn't limit anything if there's no budget
+    pub fn new(budget: Option<PowerBudget>) -> Self {
+        Self {
+            budget,
+            min_brightness: 0,
+            scale: 1.0,
+            last_frame: None,
+            status: Arc::new(SharedLimiterStatus::new(LimiterStatus::default())),
+        }
+    }
+
+    /// Keeps channels that are on at least at `min_brightness` when dimming, like
+    /// [`Calibration::min_brightness`](crate::calibration::Calibration::min_brightness),
+    /// so limiting doesn't turn dim LEDs off. They can take the frame a little over the
+    /// budget.
+    pub fn min_brightness(mut self, min_brightness: u8) -> Self {
+        self.min_brightness = (min_brightness as u16) << 8;
+        self
+    }
+
+    /// Where the limiter reports what it did to the latest frame
+    pub fn status(&self) -> Arc<SharedLimiterStatus> {
+        self.status.clone()
+    }
+
+    /// Dims `colors` in place if they'd draw more than the budget
+    pub fn limit(&mut self, colors: &mut [Rgb16]) {
+        let Some(budget) = self.budget else {
+            return;
+        };
+
+        let now = Instant::now();
+        let elapsed = self
+            .last_frame
+            .map(|last_frame| now.duration_since(last_frame).as_secs_f64())
+            .unwrap_or(0.0);
+        self.last_frame = Some(now);
+
+        // Dummies and dead LEDs are still powered, so they count towards idle current
+        let idle_amps = colors.len() as f64 * budget.led.idle_amps;
+        let color_amps: f64 = colors
+            .iter()
+            .map(|color| budget.led.color_amps(color))
+            .sum();
+
+        let target = if idle_amps + color_amps > budget.amps && color_amps > 0.0 {
+            ((budget.amps - idle_amps) / color_amps).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        self.scale = if target < self.scale {
+            target
+        } else {
+            (self.scale + RECOVERY_PER_SECOND * elapsed).min(target)
+        };
+
+        let mut limited_amps = color_amps;
+        if self.scale < 1.0 {
+            for color in colors.iter_mut() {
+                *color = color.map(|channel| {
+                    let floor = self.min_brightness.min(channel);
+                    ((channel as f64 * self.scale) as u16).max(floor)
+                });
+            }
+            limited_amps = colors
+                .iter()
+                .map(|color| budget.led.color_amps(color))
+                .sum();
+        }
+
+        self.status.store(LimiterStatus {
+            estimated_amps: idle_amps + color_amps,
+            limited_amps: idle_amps + limited_amps,
+            scale: self.scale,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limiting_keeps_lit_channels_at_min_brightness() {
+        let budget = PowerBudget {
+            amps: 0.5,
+            led: LedModel::WS2812,
+        };
+        let mut limiter = Limiter::new(Some(budget)).min_brightness(4);
+
+        let mut colors = vec![[RGB16_MAX; 3]; 100];
+        colors[0] = [6 << 8, 2 << 8, 0];
+        limiter.limit(&mut colors);
+
+        let status = limiter.status().load();
+        assert!(status.is_limiting());
+        assert!(colors[1][0] < RGB16_MAX);
+        // Above the floor it's scaled, below it it's kept as it was, and off stays off
+        assert!(colors[0][0] >= 4 << 8);
+        assert_eq!(colors[0][1], 2 << 8);
+        assert_eq!(colors[0][2], 0);
+    }
+}
@@ -1,147 +1,855 @@
 use std::{
+    cell::UnsafeCell,
+    fmt,
+    ops::Range,
+    panic::{AssertUnwindSafe, catch_unwind},
     sync::{
-        mpsc::{Receiver, Sender}, Arc, Barrier, Mutex
+        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{Receiver, Sender, channel},
     },
-    thread::spawn,
+    thread::{JoinHandle, Thread, current, panicking, park, spawn},
+    time::{Duration, Instant},
 };
 
-use palette::{Clamp, LinSrgb};
+use rayon::{ThreadPool, ThreadPoolBuilder, prelude::*};
+use serde::Deserialize;
 use shark::{
     point::Point,
     shader::{FragThree, Shader},
 };
 use smart_leds::{RGB8, SmartLedsWrite};
 
-use crate::shaders::to_linsrgb;
+use crate::{
+    calibration::{Calibration, CalibrationLut},
+    dither::{Dither, Rgb16, from_rgb8},
+    drivers::{ColorOrder, spi::WS2812_COLOR_ORDER},
+    mechanisms::{Mechanisms, Transform},
+    power::{Limiter, LimiterStatus, PowerBudget, SharedLimiterStatus},
+    scene::{Scene, ZoneShader},
+    strips::Zone,
+    triple_buffer::{Producer, triple_buffer},
+};
+
+/// How the colors of one output are adjusted before they reach its driver
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputConfig {
+    /// Channel order the strip expects on the wire
+    pub color_order: ColorOrder,
+    /// Channel order the driver sends `RGB8`s in, e.g. [`WS2812_COLOR_ORDER`]
+    pub driver_order: ColorOrder,
+    /// Most slots the driver can send in a frame, if it's limited
+    pub max_leds: Option<usize>,
+    /// How the strip's linear colors are turned into bytes
+    pub calibration: Calibration,
+    /// Whether to dither colors over frames instead of rounding them, see
+    /// [`dither`](crate::dither)
+    pub dither: bool,
+    /// Most current the output may draw, frames over it are dimmed
+    pub power_budget: Option<PowerBudget>,
+}
+impl Default for OutputConfig {
+    /// A WS2812 strip on a WS2812 driver
+    fn default() -> Self {
+        Self {
+            color_order: WS2812_COLOR_ORDER,
+            driver_order: WS2812_COLOR_ORDER,
+            max_leds: None,
+            calibration: Calibration::default(),
+            dither: true,
+            power_budget: None,
+        }
+    }
+}
+
+/// LEDs a rayon thread takes at once. Small enough that threads done with cheap parts of
+/// the robot can steal from ones stuck on expensive parts.
+const RAYON_CHUNK_SIZE: usize = 16;
 
-struct RenderCtx {
-    shader: Arc<dyn Shader<FragThree, Output = LinSrgb<f64>>>,
-    points_indexed: Vec<(usize, Point)>,
+/// How the renderer spreads shading over threads, set in `layout.toml` with e.g.
+/// `render_backend = { rayon = 4 }`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderBackend {
+    /// This many threads, each shading an equal share of the LEDs
+    Workers(usize),
+    /// A rayon pool of this many threads, stealing chunks of LEDs from each other, so
+    /// shaders that are expensive in some places don't hold up one thread
+    Rayon(usize),
+}
+impl RenderBackend {
+    pub fn threads(self) -> usize {
+        match self {
+            RenderBackend::Workers(threads) | RenderBackend::Rayon(threads) => threads,
+        }
+    }
+}
+
+/// Something that went wrong while rendering. Rendering carries on regardless.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderError {
+    /// A shader panicked on a render thread. The frame is missing that thread's LEDs, and
+    /// a render worker is restarted.
+    ShaderPanicked,
+    /// An output's driver failed to send a frame. Only the first failure in a row is
+    /// reported, see [`Renderer::output_stats`] for how many there were.
+    WriteFailed { output: String, error: String },
+    /// An output's writer panicked, so the output stays on its last frame
+    WriterDied { output: String },
+}
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::ShaderPanicked => write!(f, "a shader panicked while rendering"),
+            RenderError::WriteFailed { output, error } => {
+                write!(f, "failed to write to {output}: {error}")
+            }
+            RenderError::WriterDied { output } => {
+                write!(
+                    f,
+                    "the writer of {output} died, it won't be updated anymore"
+                )
+            }
+        }
+    }
+}
+impl std::error::Error for RenderError {}
+
+/// How an output's writes have gone since the renderer started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputStats {
+    pub frames_written: u64,
+    pub failed_writes: u64,
+    /// Whether the writer is still running
+    pub alive: bool,
+}
+
+/// [`OutputStats`], updated by the output's writer
+#[derive(Debug, Default)]
+struct OutputCounters {
+    frames_written: AtomicU64,
+    failed_writes: AtomicU64,
+    dead: AtomicBool,
+}
+
+/// Runs its closure if it's dropped while its thread panics
+struct OnPanic<F: FnMut()>(F);
+impl<F: FnMut()> Drop for OnPanic<F> {
+    fn drop(&mut self) {
+        if panicking() {
+            (self.0)();
+        }
+    }
+}
+
+/// An LED and where it is in its output and zone
+struct Led {
+    /// Index into the slots of all outputs
+    index: usize,
+    /// Index into `Renderer::output_names`
+    output: usize,
+    point: Point,
+    /// Index into `Renderer::zone_names`
+    zone: usize,
+    /// Index into `Renderer::mechanism_names`
+    mechanism: Option<usize>,
+    along: f64,
+}
+
+/// What shading needs for the current frame. The vectors keep their capacity from frame
+/// to frame, so filling them doesn't allocate.
+struct FrameCtx {
+    /// Shader of every zone, indexed like `Renderer::zone_names`
+    zone_shaders: Vec<ZoneShader>,
+    /// Current transform of every mechanism, indexed like `Renderer::mechanism_names`
+    mechanism_transforms: Vec<Transform>,
     time: f64,
+    /// Thread waiting for the workers to finish
+    renderer: Thread,
 }
 
-pub struct Renderer {
-    spi_barrier: Arc<Barrier>,
-    colors: Arc<Mutex<Vec<RGB8>>>,
+/// State shared by the renderer and its workers.
+///
+/// A frame starts when the renderer bumps `frame`, and ends when the last worker takes
+/// `pending` to 0. The renderer only writes `ctx` between frames, and the workers only
+/// read it during one.
+struct WorkerShared {
+    frame: AtomicU64,
+    /// Workers still shading the current frame
+    pending: AtomicUsize,
+    ctx: UnsafeCell<FrameCtx>,
+    /// Calibration of every output, indexed like `Renderer::output_names`
+    calibrations: Vec<CalibrationLut>,
+    /// Latest color of every slot of all outputs, see [`pack`]. Every LED is only written
+    /// by the worker it belongs to.
+    colors: Box<[AtomicU64]>,
+    /// How long every worker spent shading the latest frame, in nanoseconds. Rayon only
+    /// uses the first.
+    shading_nanos: Box<[AtomicU64]>,
+    /// LEDs of every worker, kept here so a worker can be restarted
+    chunks: Box<[Box<[Led]>]>,
+    /// Workers that panicked since the last frame
+    dead: Box<[AtomicBool]>,
+}
+impl WorkerShared {
+    /// Shades `leds` into `colors`
+    fn shade(&self, ctx: &FrameCtx, leds: &[Led]) {
+        for led in leds {
+            let pos = match led.mechanism {
+                Some(mechanism) => {
+                    let point = ctx.mechanism_transforms[mechanism].apply(&led.point);
+                    [point.x, point.y, point.z]
+                }
+                None => [led.point.x, led.point.y, led.point.z],
+            };
 
-    render_workers_barrier: Arc<Barrier>,
+            let color = ctx.zone_shaders[led.zone].shade(pos, led.along, ctx.time);
+            self.colors[led.index].store(
+                pack(self.calibrations[led.output].apply(color)),
+                Ordering::Relaxed,
+            );
+        }
+    }
+}
 
-    render_workers_ctx_senders: Vec<Sender<RenderCtx>>,
+// SAFETY: `ctx` is only written by the renderer while `pending` is 0, and only read by
+// workers while it isn't, see `Renderer::render_scene`
+unsafe impl Sync for WorkerShared {}
 
-    worker_output_colors_receiver: Receiver<Vec<(usize, RGB8)>>,
+fn pack(color: Rgb16) -> u64 {
+    (color[0] as u64) | ((color[1] as u64) << 16) | ((color[2] as u64) << 32)
 }
-impl Renderer {
+
+fn unpack(packed: u64) -> Rgb16 {
+    [packed as u16, (packed >> 16) as u16, (packed >> 32) as u16]
+}
+
+/// Shades the worker's chunk every time the renderer starts a frame
+fn render_worker(worker: usize, shared: Arc<WorkerShared>, mut last_frame: u64) {
+    // A panicking shader still has to end the frame, or the renderer would wait forever
+    let _on_panic = OnPanic(|| {
+        shared.dead[worker].store(true, Ordering::Relaxed);
+        // SAFETY: The frame isn't over until `pending` is decremented
+        let renderer = unsafe { &*shared.ctx.get() }.renderer.clone();
+        if shared.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+            renderer.unpark();
+        }
+    });
+
+    loop {
+        let frame = shared.frame.load(Ordering::Acquire);
+        if frame == last_frame {
+            // Spurious wakeups land back here
+            park();
+            continue;
+        }
+        last_frame = frame;
+
+        let start = Instant::now();
+        // SAFETY: The renderer doesn't write the context until every worker is done
+        let ctx = unsafe { &*shared.ctx.get() };
+        shared.shade(ctx, &shared.chunks[worker]);
+        shared.shading_nanos[worker].store(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+
+        // The context is the renderer's again once `pending` is decremented
+        let renderer = ctx.renderer.clone();
+        if shared.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+            renderer.unpark();
+        }
+    }
+}
+
+fn spawn_worker(worker: usize, shared: &Arc<WorkerShared>) -> Thread {
+    let shared = shared.clone();
+    // Read here rather than on the new thread, which could start after the renderer began
+    // the next frame and then wait for one more. A restarted worker waits for the next
+    // frame.
+    let last_frame = shared.frame.load(Ordering::Acquire);
+    spawn(move || render_worker(worker, shared, last_frame))
+        .thread()
+        .clone()
+}
+
+/// A strip driver together with the zones of LEDs it drives, in data order
+pub struct Output {
+    pub name: String,
+    pub zones: Vec<Zone>,
+    pub config: OutputConfig,
+
+    /// Sends a frame, or fails with the driver's error
+    write: Box<dyn FnMut(&mut dyn Iterator<Item = RGB8>) -> Result<(), String> + Send>,
+}
+impl Output {
     pub fn new<S: SmartLedsWrite<Color = RGB8> + Send + 'static>(
-        num_workers: usize,
+        name: impl Into<String>,
         mut strip: S,
+        zones: Vec<Zone>,
+        config: OutputConfig,
     ) -> Self
     where
         S::Error: std::fmt::Debug,
     {
-        let spi_barrier = Arc::new(Barrier::new(2));
-        let colors = Arc::new(Mutex::new(vec![]));
+        Self {
+            name: name.into(),
+            zones,
+            config,
 
-        let render_workers_barrier = Arc::new(Barrier::new(num_workers + 1));
-        let (output_sender, worker_output_colors_receiver) = std::sync::mpsc::channel();
+            write: Box::new(move |colors| strip.write(colors).map_err(|err| format!("{err:?}"))),
+        }
+    }
+}
+
+/// Threads shading for a [`Renderer`], see [`RenderBackend`]
+enum Shading {
+    /// Workers that each shade their own chunk of the LEDs
+    Workers(Vec<Thread>),
+    Rayon {
+        pool: ThreadPool,
+        leds: Box<[Led]>,
+    },
+}
+
+/// Renders one [`Scene`] to any number of outputs.
+///
+/// Zones are matched to the scene by name, so a zone split across outputs is shaded as
+/// one. The LEDs of all outputs are shaded together at the same `time` by the
+/// [`RenderBackend`]. Every output then gets the frame through a
+/// [triple buffer](crate::triple_buffer) and is written by its own thread, so the
+/// renderer never waits for a strip. Nothing is allocated per frame.
+///
+/// Dropping the renderer stops the writers and drops their strips, so drivers can stop
+/// their hardware.
+pub struct Renderer {
+    workers: Arc<WorkerShared>,
+    shading: Shading,
+
+    /// Where every output's writer gets its frames
+    output_frames: Vec<Producer<Vec<Rgb16>>>,
+    output_writers: Vec<JoinHandle<()>>,
+    /// Tells the writers to drop their strips and exit
+    stopping: Arc<AtomicBool>,
+    output_names: Vec<String>,
+    /// What the power limiter of every output did to its latest frame
+    output_power: Vec<Arc<SharedLimiterStatus>>,
+    output_counters: Vec<Arc<OutputCounters>>,
+    /// Where every output's slots are in the slots of all outputs
+    output_ranges: Vec<Range<usize>>,
+    zone_names: Vec<String>,
+    /// Mechanisms that any LEDs are on
+    mechanism_names: Vec<String>,
+    mechanisms: Option<Mechanisms>,
 
-        let mut render_workers_ctx_senders = Vec::new();
+    /// Errors from every thread, returned from the next render
+    errors: Receiver<RenderError>,
+    error_sender: Sender<RenderError>,
+}
+impl Renderer {
+    /// See [`validate`](crate::validation::validate) to check `outputs` first
+    pub fn new(backend: RenderBackend, outputs: Vec<Output>) -> Self {
+        let num_workers = backend.threads();
+        assert!(num_workers > 0, "the renderer needs at least one worker");
 
-        // Spawn render workers
-        for _ in 0..num_workers {
-            let (ctx_sender, ctx_receiver) = std::sync::mpsc::channel();
-            render_workers_ctx_senders.push(ctx_sender);
+        let mut output_frames = Vec::new();
+        let mut output_writers = Vec::new();
+        let stopping = Arc::new(AtomicBool::new(false));
+        let mut output_names = Vec::new();
+        let mut output_power = Vec::new();
+        let mut output_counters = Vec::new();
+        let mut output_ranges = Vec::new();
+        let mut num_slots = 0;
+        // Every slot with a point. The rest are dead or dummies and stay black.
+        let mut leds = Vec::new();
+        let mut zone_names: Vec<String> = Vec::new();
+        let mut mechanism_names: Vec<String> = Vec::new();
+        let mut calibrations = Vec::new();
+        let (error_sender, errors) = channel();
 
-            let worker_barrier = render_workers_barrier.clone();
-            let output_sender = output_sender.clone();
-            spawn(move || {
-                loop {
-                    let ctx: RenderCtx = ctx_receiver.recv().unwrap();
+        // Spawn a writer per output
+        for mut output in outputs {
+            let start = num_slots;
+            let output_index = output_names.len();
+            for zone in std::mem::take(&mut output.zones) {
+                // Zones with the same name share a shader, even across outputs
+                let zone_index = zone_names
+                    .iter()
+                    .position(|name| *name == zone.name)
+                    .unwrap_or_else(|| {
+                        zone_names.push(zone.name.clone());
+                        zone_names.len() - 1
+                    });
 
-                    let colors = ctx
-                        .points_indexed
+                let mechanism_index = zone.mechanism.as_ref().map(|mechanism| {
+                    mechanism_names
                         .iter()
-                        .map(|(i, point)| {
-                            (
-                                i,
-                                ctx.shader.shade(FragThree {
-                                    pos: [point.x, point.y, point.z],
-                                    time: ctx.time,
-                                }),
-                            )
-                        })
-                        .map(|(i, c)| (i, c.clamp()))
-                        .map(|(i, c)| {
-                            (
-                                *i,
-                                RGB8::new(
-                                    (c.red * 256.0) as u8,
-                                    (c.green * 256.0) as u8,
-                                    (c.blue * 256.0) as u8,
-                                ),
-                            )
+                        .position(|name| name == mechanism)
+                        .unwrap_or_else(|| {
+                            mechanism_names.push(mechanism.clone());
+                            mechanism_names.len() - 1
                         })
-                        .collect();
+                });
+
+                let along: Vec<f64> = zone.along().collect();
+                for (point, along) in zone.leds.into_iter().zip(along) {
+                    if let Some(point) = point {
+                        leds.push(Led {
+                            index: num_slots,
+                            output: output_index,
+                            point,
+                            zone: zone_index,
+                            mechanism: mechanism_index,
+                            along,
+                        });
+                    }
+                    num_slots += 1;
+                }
+            }
+            output_names.push(output.name.clone());
+            output_ranges.push(start..num_slots);
+            calibrations.push(output.config.calibration.lut());
+
+            let (producer, mut frames) = triple_buffer(vec![Rgb16::default(); num_slots - start]);
+            output_frames.push(producer);
+
+            let config = output.config;
+            let mut limiter =
+                Limiter::new(config.power_budget).min_brightness(config.calibration.min_brightness);
+            output_power.push(limiter.status());
+            let counters = Arc::new(OutputCounters::default());
+            output_counters.push(counters.clone());
+            let errors = error_sender.clone();
+            let name = output.name;
+            let stop = stopping.clone();
+            let writer = spawn(move || {
+                let _on_panic = OnPanic(|| {
+                    counters.dead.store(true, Ordering::Relaxed);
+                    let _ = errors.send(RenderError::WriterDied {
+                        output: name.clone(),
+                    });
+                });
+
+                let mut dither = Dither::new(config.dither);
+                let mut failing = false;
+                while !stop.load(Ordering::Acquire) {
+                    if !frames.update() {
+                        park();
+                        continue;
+                    }
 
-                    output_sender.send(colors).unwrap();
-                    worker_barrier.wait();
+                    let colors: &mut [Rgb16] = frames.front_mut();
+                    limiter.limit(colors);
+                    let result = (output.write)(
+                        &mut dither
+                            .quantize(colors)
+                            .map(|color| config.color_order.remap(color, config.driver_order)),
+                    );
+
+                    match result {
+                        Ok(()) => {
+                            counters.frames_written.fetch_add(1, Ordering::Relaxed);
+                            failing = false;
+                        }
+                        Err(error) => {
+                            counters.failed_writes.fetch_add(1, Ordering::Relaxed);
+                            if !failing {
+                                let _ = errors.send(RenderError::WriteFailed {
+                                    output: name.clone(),
+                                    error,
+                                });
+                            }
+                            failing = true;
+                        }
+                    }
                 }
             });
+            output_writers.push(writer);
         }
 
-        // Spawn SPI writer
-        spawn({
-            let barrier = spi_barrier.clone();
-            let colors = colors.clone();
-            move || {
-                loop {
-                    barrier.wait();
-                    strip.write(colors.lock().unwrap().iter().cloned()).unwrap();
-                }
+        // Every worker gets its own (possibly empty) chunk of the LEDs
+        let (chunks, rayon_leds) = match backend {
+            RenderBackend::Workers(_) => {
+                let chunk_size = leds.len().div_ceil(num_workers);
+                let mut leds = leds.into_iter();
+                let chunks = (0..num_workers)
+                    .map(|_| leds.by_ref().take(chunk_size).collect())
+                    .collect();
+                (chunks, Box::default())
             }
+            RenderBackend::Rayon(_) => (Box::default(), leds.into_boxed_slice()),
+        };
+
+        let workers = Arc::new(WorkerShared {
+            frame: AtomicU64::new(0),
+            pending: AtomicUsize::new(0),
+            ctx: UnsafeCell::new(FrameCtx {
+                zone_shaders: Vec::with_capacity(zone_names.len()),
+                mechanism_transforms: Vec::with_capacity(mechanism_names.len()),
+                time: 0.0,
+                renderer: current(),
+            }),
+            calibrations,
+            colors: (0..num_slots).map(|_| AtomicU64::new(0)).collect(),
+            shading_nanos: (0..num_workers).map(|_| AtomicU64::new(0)).collect(),
+            chunks,
+            dead: (0..num_workers).map(|_| AtomicBool::new(false)).collect(),
         });
 
+        let shading = match backend {
+            RenderBackend::Workers(_) => Shading::Workers(
+                (0..num_workers)
+                    .map(|worker| spawn_worker(worker, &workers))
+                    .collect(),
+            ),
+            RenderBackend::Rayon(_) => Shading::Rayon {
+                pool: ThreadPoolBuilder::new()
+                    .num_threads(num_workers)
+                    .thread_name(|i| format!("render-{i}"))
+                    .build()
+                    .expect("failed to start the render thread pool"),
+                leds: rayon_leds,
+            },
+        };
+
         Self {
-            spi_barrier,
-            colors,
+            workers,
+            shading,
 
-            render_workers_barrier,
-            render_workers_ctx_senders,
+            output_frames,
+            output_writers,
+            stopping,
+            output_names,
+            output_power,
+            output_counters,
+            output_ranges,
+            zone_names,
+            mechanism_names,
+            mechanisms: None,
 
-            worker_output_colors_receiver,
+            errors,
+            error_sender,
         }
     }
 
-    pub fn render(&self, shader: impl Shader<FragThree> + 'static, points: Vec<Point>, time: f64) {
-        let num_workers = self.render_workers_ctx_senders.len();
-        let shader = Arc::new(to_linsrgb(shader));
+    /// Renders `shader` on every zone
+    pub fn render(
+        &mut self,
+        shader: impl Shader<FragThree> + 'static,
+        time: f64,
+    ) -> Result<(), RenderError> {
+        self.render_scene(&Scene::new(shader), time)
+    }
 
-        let points_indexed = points.into_iter().enumerate().collect::<Vec<_>>();
-        for (chunk, sender) in points_indexed
-            .chunks(points_indexed.len() / num_workers)
-            .zip(&self.render_workers_ctx_senders)
+    /// Renders a frame of `scene`. The frame is rendered even if this fails, the error is
+    /// the oldest one from any thread since the last frame, and the rest are returned
+    /// from the next ones.
+    pub fn render_scene(&mut self, scene: &Scene, time: f64) -> Result<(), RenderError> {
         {
-            let points_indexed = chunk.to_vec();
+            // SAFETY: Between frames every worker is parked or on its way to park, and
+            // the rayon pool is idle, none of them touch the context
+            let ctx = unsafe { &mut *self.workers.ctx.get() };
 
-            let ctx = RenderCtx {
-                shader: shader.clone(),
-                points_indexed: points_indexed.clone(),
-                time,
-            };
+            ctx.zone_shaders.clear();
+            ctx.zone_shaders.extend(
+                self.zone_names
+                    .iter()
+                    .map(|name| scene.zone_shader(name).clone()),
+            );
+
+            ctx.mechanism_transforms.clear();
+            ctx.mechanism_transforms
+                .extend(
+                    self.mechanism_names
+                        .iter()
+                        .map(|name| match &self.mechanisms {
+                            Some(mechanisms) => mechanisms.transform(name),
+                            None => Transform::IDENTITY,
+                        }),
+                );
 
-            sender.send(ctx).unwrap();
+            ctx.time = time;
+            ctx.renderer = current();
         }
-        self.render_workers_barrier.wait();
 
-        let mut new_colors = vec![RGB8::default(); points_indexed.len()];
-        for _ in 0..num_workers {
-            let colors = self.worker_output_colors_receiver.recv().unwrap();
-            for (i, c) in colors {
-                new_colors[i] = c;
+        match &mut self.shading {
+            Shading::Workers(threads) => {
+                self.workers.pending.store(threads.len(), Ordering::Relaxed);
+                self.workers.frame.fetch_add(1, Ordering::Release);
+                for worker in threads.iter() {
+                    worker.unpark();
+                }
+                while self.workers.pending.load(Ordering::Acquire) != 0 {
+                    park();
+                }
+
+                for (worker, dead) in self.workers.dead.iter().enumerate() {
+                    if dead.swap(false, Ordering::Relaxed) {
+                        threads[worker] = spawn_worker(worker, &self.workers);
+                        let _ = self.error_sender.send(RenderError::ShaderPanicked);
+                    }
+                }
+            }
+            Shading::Rayon { pool, leds } => {
+                let start = Instant::now();
+                // SAFETY: The context was written above and isn't touched until the pool
+                // is done
+                let ctx = unsafe { &*self.workers.ctx.get() };
+                let shared = &*self.workers;
+                // Rayon hands a panic back to us once every thread is done
+                let shaded = catch_unwind(AssertUnwindSafe(|| {
+                    pool.install(|| {
+                        leds.par_chunks(RAYON_CHUNK_SIZE)
+                            .for_each(|chunk| shared.shade(ctx, chunk));
+                    })
+                }));
+                if shaded.is_err() {
+                    let _ = self.error_sender.send(RenderError::ShaderPanicked);
+                }
+                shared.shading_nanos[0].store(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
             }
         }
-        // dbg!(&new_colors);
-        *self.colors.lock().unwrap() = new_colors;
 
-        self.spi_barrier.wait();
+        for (frames, range) in self.output_frames.iter_mut().zip(&self.output_ranges) {
+            for (color, packed) in frames
+                .back_mut()
+                .iter_mut()
+                .zip(&self.workers.colors[range.clone()])
+            {
+                *color = unpack(packed.load(Ordering::Relaxed));
+            }
+            frames.publish();
+        }
+        self.wake_writers();
+
+        self.next_error()
+    }
+
+    /// The oldest error that wasn't returned yet
+    fn next_error(&self) -> Result<(), RenderError> {
+        match self.errors.try_recv() {
+            Ok(error) => Err(error),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Starts every output's writer on its latest frame. They're woken together, so all
+    /// outputs switch frames at about the same time.
+    fn wake_writers(&self) {
+        for writer in &self.output_writers {
+            writer.thread().unpark();
+        }
+    }
+
+    /// How long the slowest worker, or the whole rayon pool, spent shading the latest
+    /// frame
+    pub fn shading_time(&self) -> Duration {
+        let nanos = self
+            .workers
+            .shading_nanos
+            .iter()
+            .map(|nanos| nanos.load(Ordering::Relaxed))
+            .max()
+            .unwrap_or(0);
+        Duration::from_nanos(nanos)
+    }
+
+    /// Moves LEDs on mechanisms with them from now on. Until then they stay where the
+    /// layout put them.
+    pub fn attach_mechanisms(&mut self, mechanisms: Mechanisms) {
+        for name in &self.mechanism_names {
+            if mechanisms.get(name).is_none() {
+                println!("LEDs are on mechanism {name:?}, which isn't defined");
+            }
+        }
+
+        self.mechanisms = Some(mechanisms);
+    }
+
+    /// What the power limiter of every output did to its latest frame, by output name
+    pub fn power(&self) -> impl Iterator<Item = (&str, LimiterStatus)> {
+        self.output_names
+            .iter()
+            .zip(&self.output_power)
+            .map(|(name, status)| (name.as_str(), status.load()))
+    }
+
+    /// How the writes of every output have gone, by output name
+    pub fn output_stats(&self) -> impl Iterator<Item = (&str, OutputStats)> {
+        self.output_names
+            .iter()
+            .zip(&self.output_counters)
+            .map(|(name, counters)| {
+                (
+                    name.as_str(),
+                    OutputStats {
+                        frames_written: counters.frames_written.load(Ordering::Relaxed),
+                        failed_writes: counters.failed_writes.load(Ordering::Relaxed),
+                        alive: !counters.dead.load(Ordering::Relaxed),
+                    },
+                )
+            })
+    }
+
+    /// Number of slots on the output called `name`
+    pub fn num_slots(&self, name: &str) -> Option<usize> {
+        let i = self.output_names.iter().position(|output| output == name)?;
+        Some(self.output_ranges[i].len())
+    }
+
+    /// Writes `color(output name, slot)` to every slot of every output, without shading.
+    /// Dead LEDs and dummies are included, e.g. for finding out where every slot is.
+    pub fn render_slots(&mut self, color: impl Fn(&str, usize) -> RGB8) -> Result<(), RenderError> {
+        for (frames, name) in self.output_frames.iter_mut().zip(&self.output_names) {
+            for (slot, c) in frames.back_mut().iter_mut().enumerate() {
+                *c = from_rgb8(color(name, slot));
+            }
+            frames.publish();
+        }
+        self.wake_writers();
+
+        self.next_error()
+    }
+}
+impl Drop for Renderer {
+    /// Waits for every writer to finish the frame it's on and drop its strip
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::Release);
+        for writer in self.output_writers.drain(..) {
+            writer.thread().unpark();
+            // A writer that panicked already reported it
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use palette::LinSrgb;
+    use shark::shader::{FragOne, IntoShader};
+
+    use super::*;
+    use crate::{
+        dither::round,
+        drivers::capture::{CaptureStrip, Captures},
+    };
+
+    /// A strip that sets `dropped` when it's dropped
+    struct DropStrip {
+        dropped: Arc<AtomicBool>,
+    }
+    impl SmartLedsWrite for DropStrip {
+        type Error = Infallible;
+        type Color = RGB8;
+
+        fn write<T, I>(&mut self, _iterator: T) -> Result<(), Self::Error>
+        where
+            T: IntoIterator<Item = I>,
+            I: Into<Self::Color>,
+        {
+            Ok(())
+        }
+    }
+    impl Drop for DropStrip {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn dropping_the_renderer_drops_the_strips() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let strip = DropStrip {
+            dropped: dropped.clone(),
+        };
+        let output = Output::new("strip", strip, Vec::new(), OutputConfig::default());
+        let mut renderer = Renderer::new(RenderBackend::Workers(1), vec![output]);
+        renderer.render_slots(|_, _| RGB8::default()).unwrap();
+
+        drop(renderer);
+        assert!(dropped.load(Ordering::Relaxed));
+    }
+
+    /// A zone of `leds` LEDs, each somewhere else so its color gives away which it is
+    fn zone(name: &str, leds: usize, y: f64) -> Zone {
+        Zone::new(
+            name,
+            (0..leds).map(|i| Point {
+                x: i as f64 / leds as f64,
+                y,
+                z: 0.0,
+            }),
+        )
+    }
+
+    fn outputs() -> Vec<(&'static str, Vec<Zone>)> {
+        vec![
+            ("box_tube", vec![zone("box_tube", 37, 0.2)]),
+            (
+                "underglow",
+                vec![
+                    zone("underglow_front", 23, 0.4),
+                    zone("underglow_side", 30, 0.6),
+                ],
+            ),
+        ]
+    }
+
+    /// Red and green from where an LED is, and blue along the underglow's side
+    fn scene() -> Scene {
+        let position = |frag: FragThree| LinSrgb::new(frag.pos[0], frag.pos[1], 0.0);
+        let along = |frag: FragOne| LinSrgb::new(0.0, 0.0, frag.pos);
+        Scene::new(position.into_shader()).zone_along("underglow_side", along.into_shader())
+    }
+
+    /// The frame every output gets from rendering [`scene`] once on `backend`
+    fn render(backend: RenderBackend) -> Vec<Vec<RGB8>> {
+        let config = OutputConfig {
+            dither: false,
+            ..OutputConfig::default()
+        };
+        let (outputs, captures): (Vec<Output>, Vec<Captures>) = outputs()
+            .into_iter()
+            .map(|(name, zones)| {
+                let strip = CaptureStrip::new();
+                let captures = strip.captures();
+                (Output::new(name, strip, zones, config), captures)
+            })
+            .unzip();
+
+        let mut renderer = Renderer::new(backend, outputs);
+        renderer.render_scene(&scene(), 0.0).unwrap();
+
+        captures
+            .iter()
+            .map(|captures| {
+                assert!(captures.wait_for(1, Duration::from_secs(5)));
+                captures.frames()[0].colors.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn backends_render_the_same_frames() {
+        let workers = render(RenderBackend::Workers(3));
+        let rayon = render(RenderBackend::Rayon(3));
+        assert_eq!(workers, rayon);
+
+        // Every output gets exactly its own LEDs, in order
+        let lut = Calibration::default().lut();
+        let scene = scene();
+        for ((_, zones), frame) in outputs().iter().zip(&workers) {
+            let expected: Vec<RGB8> = zones
+                .iter()
+                .flat_map(|zone| {
+                    let shader = scene.zone_shader(&zone.name);
+                    zone.leds.iter().zip(zone.along()).map(|(point, along)| {
+                        let point = point.unwrap();
+                        let color = shader.shade([point.x, point.y, point.z], along, 0.0);
+                        round(lut.apply(color))
+                    })
+                })
+                .collect();
+            assert_eq!(*frame, expected);
+        }
     }
 }
@@ -0,0 +1,61 @@
+use std::{collections::HashMap, sync::Arc};
+
+use palette::LinSrgb;
+use shark::shader::{FragOne, FragThree, Shader};
+
+use crate::shaders::to_linsrgb;
+
+/// How the LEDs of a zone are shaded
+#[derive(Clone)]
+pub enum ZoneShader {
+    /// By their position on the robot
+    Position(Arc<dyn Shader<FragThree, Output = LinSrgb<f64>>>),
+    /// By their position along their segment, see [`Zone::along`](crate::strips::Zone::along)
+    Along(Arc<dyn Shader<FragOne, Output = LinSrgb<f64>>>),
+}
+impl ZoneShader {
+    pub fn shade(&self, pos: [f64; 3], along: f64, time: f64) -> LinSrgb<f64> {
+        match self {
+            ZoneShader::Position(shader) => shader.shade(FragThree { pos, time }),
+            ZoneShader::Along(shader) => shader.shade(FragOne { pos: along, time }),
+        }
+    }
+}
+
+/// A shader for the whole robot, with any zone free to use its own instead.
+///
+/// ```ignore
+/// Scene::new(flowy_rainbow())
+///     .zone_along("underglow_left", conveyor(..))
+/// ```
+#[derive(Clone)]
+pub struct Scene {
+    base: ZoneShader,
+    zones: HashMap<String, ZoneShader>,
+}
+impl Scene {
+    /// Shades every zone without its own shader
+    pub fn new(base: impl Shader<FragThree> + 'static) -> Self {
+        Self {
+            base: ZoneShader::Position(Arc::new(to_linsrgb(base))),
+            zones: HashMap::new(),
+        }
+    }
+
+    /// Shades the zone by position along its segments, so one dimensional shaders run
+    /// from one end of a strip to the other no matter where it is on the robot
+    pub fn zone_along(
+        mut self,
+        name: impl Into<String>,
+        shader: impl Shader<FragOne> + 'static,
+    ) -> Self {
+        self.zones
+            .insert(name.into(), ZoneShader::Along(Arc::new(to_linsrgb(shader))));
+        self
+    }
+
+    /// The shader for the zone called `name`
+    pub fn zone_shader(&self, name: &str) -> &ZoneShader {
+        self.zones.get(name).unwrap_or(&self.base)
+    }
+}
@@ -59,8 +59,11 @@ pub fn random_pride_flag() -> impl Shader<FragOne> {
     box_shader(flag())
 }
 
-fn coral_state_indicator(coral_state: CoralState) -> impl Shader<FragThree> {
-    (move |frag: FragThree| match coral_state {
+/// The coral state as a conveyor running along a strip
+pub fn coral_state_conveyor(
+    coral_state: CoralState,
+) -> impl Shader<FragOne, Output = LinSrgb<f64>> {
+    (move |frag: FragOne| match coral_state {
         // CoralState::None => flowy_rainbow().to_linsrgb().shade(frag),
         CoralState::None => conveyor(
             color(LinSrgb::new(0.0, 0.4, 0.8)),
@@ -70,8 +73,6 @@ fn coral_state_indicator(coral_state: CoralState) -> impl Shader<FragThree> {
         )
         .to_linsrgb()
         .volume_blur(0.03, 8)
-        .extrude()
-        .extrude()
         .shade(frag),
 
         CoralState::Transit => conveyor(
@@ -81,8 +82,6 @@ fn coral_state_indicator(coral_state: CoralState) -> impl Shader<FragThree> {
             0.5,
         )
         .to_linsrgb()
-        .extrude()
-        .extrude()
         .shade(frag),
 
         CoralState::Held => conveyor(
@@ -93,13 +92,15 @@ fn coral_state_indicator(coral_state: CoralState) -> impl Shader<FragThree> {
         )
         .to_linsrgb()
         .volume_blur(0.1, 12)
-        .extrude()
-        .extrude()
         .shade(frag),
     })
     .into_shader()
 }
 
+fn coral_state_indicator(coral_state: CoralState) -> impl Shader<FragThree> {
+    coral_state_conveyor(coral_state).extrude().extrude()
+}
+
 fn auto_align_indicator(
     movement_state: MovementState,
     relative_pos: [Length; 2],
@@ -1,6 +1,10 @@
+use std::ops::Range;
+
 use shark::point::{Point, primitives::line};
 use shrewnit::{Dimension, Inches, Meters, ScalarExt, to};
 
+use crate::mechanisms::Mechanism;
+
 pub fn box_tube_to_intake() -> impl Iterator<Item = Point> + Clone {
     let segment_length = 18.0f64 * Inches;
     let leds_per_segment = 27;
@@ -85,73 +89,155 @@ pub fn box_tube_to_intake() -> impl Iterator<Item = Point> + Clone {
     // ))
 }
 
-pub fn underglow() -> impl Iterator<Item = Point> + Clone {
-    let horizontal_offset = 13.0f64 * Inches;
-    let vertical_offset = 14.0f64 * Inches;
+const UNDERGLOW_HORIZONTAL_OFFSET: f64 = 13.0;
+const UNDERGLOW_VERTICAL_OFFSET: f64 = 14.0;
 
+pub fn underglow_front() -> impl Iterator<Item = Point> + Clone {
     line(
         Point {
-            x: -horizontal_offset.to::<Meters>() / 2.0,
+            x: -UNDERGLOW_HORIZONTAL_OFFSET.inches().to::<Meters>() / 2.0,
             y: 0.0,
-            z: vertical_offset.to::<Meters>(),
+            z: UNDERGLOW_VERTICAL_OFFSET.inches().to::<Meters>(),
         },
         Point {
-            x: horizontal_offset.to::<Meters>() / 2.0,
+            x: UNDERGLOW_HORIZONTAL_OFFSET.inches().to::<Meters>() / 2.0,
             y: 0.0,
-            z: vertical_offset.to::<Meters>(),
+            z: UNDERGLOW_VERTICAL_OFFSET.inches().to::<Meters>(),
         },
         23,
     )
-    .chain(line(
+}
+
+pub fn underglow_right() -> impl Iterator<Item = Point> + Clone {
+    line(
         Point {
-            x: horizontal_offset.to::<Meters>(),
+            x: UNDERGLOW_HORIZONTAL_OFFSET.inches().to::<Meters>(),
             y: 0.0,
             z: 16.0.inches().to::<Meters>() / 2.0,
         },
         Point {
-            x: horizontal_offset.to::<Meters>(),
+            x: UNDERGLOW_HORIZONTAL_OFFSET.inches().to::<Meters>(),
             y: 0.0,
             z: -16.0.inches().to::<Meters>() / 2.0,
         },
         30,
-    ))
-    .chain(line(
+    )
+}
+
+pub fn underglow_back_right() -> impl Iterator<Item = Point> + Clone {
+    line(
         Point {
-            x: horizontal_offset.to::<Meters>(),
+            x: UNDERGLOW_HORIZONTAL_OFFSET.inches().to::<Meters>(),
             y: 0.0,
-            z: -vertical_offset.to::<Meters>(),
+            z: -UNDERGLOW_VERTICAL_OFFSET.inches().to::<Meters>(),
         },
         Point {
             x: 7.0.inches().to::<Meters>(),
             y: 0.0,
-            z: -vertical_offset.to::<Meters>(),
+            z: -UNDERGLOW_VERTICAL_OFFSET.inches().to::<Meters>(),
         },
         12,
-    ))
-    .chain(line(
+    )
+}
+
+pub fn underglow_back_left() -> impl Iterator<Item = Point> + Clone {
+    line(
         Point {
             x: -7.0.inches().to::<Meters>(),
             y: 0.0,
-            z: -vertical_offset.to::<Meters>(),
+            z: -UNDERGLOW_VERTICAL_OFFSET.inches().to::<Meters>(),
         },
         Point {
             x: -5.0.inches().to::<Meters>(),
             y: 0.0,
-            z: -vertical_offset.to::<Meters>(),
+            z: -UNDERGLOW_VERTICAL_OFFSET.inches().to::<Meters>(),
         },
         3,
-    ))
-    .chain(line(
+    )
+}
+
+pub fn underglow_left() -> impl Iterator<Item = Point> + Clone {
+    line(
         Point {
-            x: -horizontal_offset.to::<Meters>(),
+            x: -UNDERGLOW_HORIZONTAL_OFFSET.inches().to::<Meters>(),
             y: 0.0,
             z: -16.0.inches().to::<Meters>() / 2.0,
         },
         Point {
-            x: -horizontal_offset.to::<Meters>(),
+            x: -UNDERGLOW_HORIZONTAL_OFFSET.inches().to::<Meters>(),
             y: 0.0,
             z: 16.0.inches().to::<Meters>() / 2.0,
         },
         3,
-    ))
+    )
+}
+
+/// A named run of LEDs that a [`Scene`](crate::scene::Scene) can give its own shader
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub name: String,
+    /// Name of the [`Mechanism`] the zone moves with
+    pub mechanism: Option<String>,
+    /// Every slot on the driver output, in data order. Dead LEDs and dummy pixels have
+    /// no point and are kept black.
+    pub leds: Vec<Option<Point>>,
+    /// Slots of each segment the zone is made of, in data order
+    pub segments: Vec<Range<usize>>,
+}
+impl Zone {
+    pub fn new(name: impl Into<String>, points: impl IntoIterator<Item = Point>) -> Self {
+        let leds: Vec<_> = points.into_iter().map(Some).collect();
+        Self {
+            name: name.into(),
+            mechanism: None,
+            segments: std::iter::once(0..leds.len()).collect(),
+            leds,
+        }
+    }
+
+    /// Moves the zone with the mechanism called `name`
+    pub fn on_mechanism(mut self, name: impl Into<String>) -> Self {
+        self.mechanism = Some(name.into());
+        self
+    }
+
+    /// Position of every slot along its segment, from 0 at the segment's first LED to 1
+    /// at its last. Dead LEDs and dummy pixels don't count and get 0.
+    pub fn along(&self) -> impl Iterator<Item = f64> + '_ {
+        self.segments.iter().flat_map(|segment| {
+            let leds = &self.leds[segment.clone()];
+            let last = leds.iter().flatten().count().saturating_sub(1).max(1) as f64;
+            leds.iter().scan(0, move |positioned, led| {
+                Some(match led {
+                    Some(_) => {
+                        *positioned += 1;
+                        (*positioned - 1) as f64 / last
+                    }
+                    None => 0.0,
+                })
+            })
+        })
+    }
+}
+
+pub fn box_tube_zones() -> Vec<Zone> {
+    vec![Zone::new("box_tube", box_tube_to_intake()).on_mechanism("elevator")]
+}
+
+pub fn mechanisms() -> Vec<Mechanism> {
+    vec![Mechanism::linear(
+        "elevator",
+        "RGB/Mechanisms/Elevator Height",
+        [0.0, 1.0, 0.0],
+    )]
+}
+
+pub fn underglow_zones() -> Vec<Zone> {
+    vec![
+        Zone::new("underglow_front", underglow_front()),
+        Zone::new("underglow_right", underglow_right()),
+        Zone::new("underglow_back_right", underglow_back_right()),
+        Zone::new("underglow_back_left", underglow_back_left()),
+        Zone::new("underglow_left", underglow_left()),
+    ]
 }
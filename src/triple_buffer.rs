@@ -0,0 +1,92 @@
+//! A lock-free single producer, single consumer triple buffer, for handing frames from
+//! the renderer to an output's writer.
+//!
+//! The renderer fills the back buffer and publishes it as the middle buffer. The writer
+//! swaps the middle buffer for its front buffer whenever there's a new one. Neither side
+//! ever waits for the other: a slow writer skips to the latest frame, and the renderer
+//! never blocks on a writer that's still sending.
+
+use std::{
+    cell::UnsafeCell,
+    sync::{
+        Arc,
+        atomic::{AtomicU8, Ordering},
+    },
+};
+
+const INDEX_MASK: u8 = 0b011;
+/// Set in `middle` when the middle buffer was published and not read yet
+const NEW: u8 = 0b100;
+
+struct Shared<T> {
+    buffers: [UnsafeCell<T>; 3],
+    /// Index of the middle buffer, and [`NEW`]
+    middle: AtomicU8,
+}
+
+// SAFETY: Every buffer is owned by exactly one of the back, middle or front at a time, and
+// ownership only moves through `middle` with acquire/release ordering. The back buffer is
+// only touched through the `Producer` and the front buffer through the `Consumer`.
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+/// Creates a triple buffer starting out with a clone of `initial` in every buffer
+pub fn triple_buffer<T: Clone>(initial: T) -> (Producer<T>, Consumer<T>) {
+    let shared = Arc::new(Shared {
+        buffers: [
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial),
+        ],
+        middle: AtomicU8::new(1),
+    });
+
+    (
+        Producer {
+            shared: shared.clone(),
+            back: 0,
+        },
+        Consumer { shared, front: 2 },
+    )
+}
+
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+    back: u8,
+}
+impl<T> Producer<T> {
+    /// The buffer being filled. It holds whatever frame was in it before, not necessarily
+    /// the last one published.
+    pub fn back_mut(&mut self) -> &mut T {
+        // SAFETY: Only the producer has the back buffer
+        unsafe { &mut *self.shared.buffers[self.back as usize].get() }
+    }
+
+    /// Hands the back buffer to the consumer, replacing a frame it hasn't taken yet
+    pub fn publish(&mut self) {
+        let old_middle = self.shared.middle.swap(self.back | NEW, Ordering::AcqRel);
+        self.back = old_middle & INDEX_MASK;
+    }
+}
+
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+    front: u8,
+}
+impl<T> Consumer<T> {
+    /// Takes the latest published frame into the front buffer. `false` if nothing was
+    /// published since the last time.
+    pub fn update(&mut self) -> bool {
+        if self.shared.middle.load(Ordering::Relaxed) & NEW == 0 {
+            return false;
+        }
+
+        let old_middle = self.shared.middle.swap(self.front, Ordering::AcqRel);
+        self.front = old_middle & INDEX_MASK;
+        true
+    }
+
+    pub fn front_mut(&mut self) -> &mut T {
+        // SAFETY: Only the consumer has the front buffer
+        unsafe { &mut *self.shared.buffers[self.front as usize].get() }
+    }
+}
@@ -0,0 +1,462 @@
+//! Checks the outputs of a layout before anything uses them, so a broken layout is
+//! caught at startup instead of as a panic or a dark strip.
+
+use std::fmt;
+
+use shark::point::Point;
+
+use crate::{renderer::OutputConfig, strips::Zone};
+
+/// LEDs closer than this (in meters) are probably the same LED listed twice. The
+/// densest strips we use are 144/m, about 7mm apart.
+const DUPLICATE_DISTANCE: f64 = 0.003;
+
+/// Near-duplicate pairs listed in the report, the rest are only counted
+const MAX_LISTED_DUPLICATES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+impl Bounds {
+    fn of(points: impl IntoIterator<Item = [f64; 3]>) -> Option<Self> {
+        points.into_iter().fold(None, |bounds, pos| {
+            let Bounds { min, max } = bounds.unwrap_or(Bounds { min: pos, max: pos });
+            Some(Bounds {
+                min: [0, 1, 2].map(|i| min[i].min(pos[i])),
+                max: [0, 1, 2].map(|i| max[i].max(pos[i])),
+            })
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputReport {
+    pub name: String,
+    /// Slots on the driver, including dead LEDs and dummies
+    pub slots: usize,
+    /// Slots with a point
+    pub leds: usize,
+    pub bounds: Option<Bounds>,
+    /// Current with every LED at full white
+    pub max_current_amps: f64,
+    /// Current the power limiter keeps the output under, if it has a budget
+    pub budget_amps: Option<f64>,
+}
+
+/// An LED by output name and slot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedRef {
+    pub output: String,
+    pub slot: usize,
+}
+impl fmt::Display for LedRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]", self.output, self.slot)
+    }
+}
+
+/// Configurations the renderer can't run
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    NoWorkers,
+    NoOutputs,
+    DuplicateOutput(String),
+    TooManyLeds {
+        output: String,
+        slots: usize,
+        max: usize,
+    },
+    /// A point with a NaN or infinite coordinate, which would poison every shader using it
+    NonFinitePoint(LedRef),
+}
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::NoWorkers => write!(f, "the renderer needs at least one worker"),
+            ValidationError::NoOutputs => write!(f, "there are no outputs to render to"),
+            ValidationError::DuplicateOutput(name) => {
+                write!(f, "more than one output is called {name:?}")
+            }
+            ValidationError::TooManyLeds { output, slots, max } => write!(
+                f,
+                "{output} has {slots} LEDs, but its driver can only send {max}"
+            ),
+            ValidationError::NonFinitePoint(led) => {
+                write!(f, "{led} has a position that isn't a finite number")
+            }
+        }
+    }
+}
+
+/// Things that will render, but probably not as intended
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationWarning {
+    EmptyOutput(String),
+    NearDuplicates {
+        /// The first few pairs
+        pairs: Vec<(LedRef, LedRef)>,
+        count: usize,
+    },
+    /// The LEDs of an output draw more than its power budget even when they're off, so
+    /// the limiter keeps it dark
+    BudgetBelowIdle {
+        output: String,
+        idle_amps: f64,
+        budget_amps: f64,
+    },
+}
+impl fmt::Display for ValidationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationWarning::EmptyOutput(name) => write!(f, "{name} has no LEDs"),
+            ValidationWarning::NearDuplicates { pairs, count } => {
+                write!(
+                    f,
+                    "{count} pairs of LEDs are within {}mm of each other:",
+                    DUPLICATE_DISTANCE * 1000.0
+                )?;
+                for (a, b) in pairs {
+                    write!(f, " {a}/{b}")?;
+                }
+                if *count > pairs.len() {
+                    write!(f, " ...")?;
+                }
+                Ok(())
+            }
+            ValidationWarning::BudgetBelowIdle {
+                output,
+                idle_amps,
+                budget_amps,
+            } => write!(
+                f,
+                "{output} draws {idle_amps:.2}A while off, over its {budget_amps:.1}A power budget"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub outputs: Vec<OutputReport>,
+    pub bounds: Option<Bounds>,
+    /// Current of every output at full white, or at its budget if that's lower
+    pub max_current_amps: f64,
+    pub errors: Vec<ValidationError>,
+    pub warnings: Vec<ValidationWarning>,
+}
+impl ValidationReport {
+    /// Whether the renderer can run these outputs at all
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format_bounds = |bounds: &Option<Bounds>| match bounds {
+            Some(Bounds { min, max }) => format!(
+                "({:.3}, {:.3}, {:.3}) to ({:.3}, {:.3}, {:.3}) m",
+                min[0], min[1], min[2], max[0], max[1], max[2]
+            ),
+            None => "empty".to_owned(),
+        };
+
+        writeln!(f, "Layout:")?;
+        for output in &self.outputs {
+            let budget = match output.budget_amps {
+                Some(budget) => format!(" (limited to {budget:.1}A)"),
+                None => String::new(),
+            };
+            writeln!(
+                f,
+                "  {}: {} slots, {} LEDs, up to {:.1}A{budget}, {}",
+                output.name,
+                output.slots,
+                output.leds,
+                output.max_current_amps,
+                format_bounds(&output.bounds)
+            )?;
+        }
+        writeln!(
+            f,
+            "  total: up to {:.1}A, {}",
+            self.max_current_amps,
+            format_bounds(&self.bounds)
+        )?;
+
+        for warning in &self.warnings {
+            writeln!(f, "warning: {warning}")?;
+        }
+        for error in &self.errors {
+            writeln!(f, "error: {error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Checks `outputs`, given as (name, zones, config), for a renderer with `num_workers`
+/// workers
+pub fn validate(
+    outputs: &[(&str, Vec<Zone>, OutputConfig)],
+    num_workers: usize,
+) -> ValidationReport {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    if num_workers == 0 {
+        errors.push(ValidationError::NoWorkers);
+    }
+    if outputs.is_empty() {
+        errors.push(ValidationError::NoOutputs);
+    }
+
+    let mut output_reports = Vec::new();
+    // Every LED with a point, for finding duplicates across outputs
+    let mut all_leds: Vec<(LedRef, [f64; 3])> = Vec::new();
+
+    for (i, (name, zones, config)) in outputs.iter().enumerate() {
+        if outputs[..i].iter().any(|(other, ..)| other == name) {
+            errors.push(ValidationError::DuplicateOutput(name.to_string()));
+        }
+
+        let slots: Vec<&Option<Point>> = zones.iter().flat_map(|zone| &zone.leds).collect();
+        let mut positions = Vec::new();
+        for (slot, point) in slots.iter().enumerate() {
+            let Some(point) = point else {
+                continue;
+            };
+            let led = LedRef {
+                output: name.to_string(),
+                slot,
+            };
+            let pos = [point.x, point.y, point.z];
+
+            if !pos.iter().all(|c| c.is_finite()) {
+                errors.push(ValidationError::NonFinitePoint(led));
+                continue;
+            }
+
+            positions.push(pos);
+            all_leds.push((led, pos));
+        }
+
+        if slots.is_empty() {
+            warnings.push(ValidationWarning::EmptyOutput(name.to_string()));
+        }
+        match config.max_leds {
+            Some(max) if slots.len() > max => errors.push(ValidationError::TooManyLeds {
+                output: name.to_string(),
+                slots: slots.len(),
+                max,
+            }),
+            _ => {}
+        }
+
+        // Dead LEDs and dummies are powered but never lit
+        let budget = config.power_budget;
+        let led = budget.map(|budget| budget.led).unwrap_or_default();
+        let idle_amps = slots.len() as f64 * led.idle_amps;
+        if let Some(budget) = budget.filter(|budget| budget.amps <= idle_amps) {
+            warnings.push(ValidationWarning::BudgetBelowIdle {
+                output: name.to_string(),
+                idle_amps,
+                budget_amps: budget.amps,
+            });
+        }
+
+        output_reports.push(OutputReport {
+            name: name.to_string(),
+            slots: slots.len(),
+            leds: positions.len(),
+            bounds: Bounds::of(positions.iter().copied()),
+            max_current_amps: idle_amps + positions.len() as f64 * (led.max_amps() - led.idle_amps),
+            budget_amps: budget.map(|budget| budget.amps),
+        });
+    }
+
+    // Sorted by x, so only LEDs within the distance along x have to be compared
+    all_leds.sort_by(|(_, a), (_, b)| a[0].total_cmp(&b[0]));
+    let mut duplicates = Vec::new();
+    let mut count = 0;
+    for (i, (a, a_pos)) in all_leds.iter().enumerate() {
+        for (b, b_pos) in &all_leds[i + 1..] {
+            if b_pos[0] - a_pos[0] > DUPLICATE_DISTANCE {
+                break;
+            }
+            if distance(*a_pos, *b_pos) <= DUPLICATE_DISTANCE {
+                count += 1;
+                if duplicates.len() < MAX_LISTED_DUPLICATES {
+                    duplicates.push((a.clone(), b.clone()));
+                }
+            }
+        }
+    }
+    if count > 0 {
+        warnings.push(ValidationWarning::NearDuplicates {
+            pairs: duplicates,
+            count,
+        });
+    }
+
+    ValidationReport {
+        bounds: Bounds::of(all_leds.iter().map(|(_, pos)| *pos)),
+        max_current_amps: output_reports
+            .iter()
+            .map(|output| match output.budget_amps {
+                Some(budget) => output.max_current_amps.min(budget),
+                None => output.max_current_amps,
+            })
+            .sum(),
+        outputs: output_reports,
+        errors,
+        warnings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power::PowerBudget;
+
+    /// A zone of `leds` LEDs 1cm apart along x, starting at `x`
+    fn zone(name: &str, x: f64, leds: usize) -> Zone {
+        Zone::new(
+            name,
+            (0..leds).map(|i| Point {
+                x: x + i as f64 * 0.01,
+                y: 0.0,
+                z: 0.0,
+            }),
+        )
+    }
+
+    #[test]
+    fn a_good_layout_passes() {
+        let outputs = [
+            (
+                "box_tube",
+                vec![zone("box_tube", 0.0, 10)],
+                OutputConfig::default(),
+            ),
+            (
+                "underglow",
+                vec![zone("underglow", 1.0, 10)],
+                OutputConfig::default(),
+            ),
+        ];
+        let report = validate(&outputs, 2);
+        assert!(report.is_ok());
+        assert_eq!(report.errors, []);
+        assert_eq!(report.warnings, []);
+        assert_eq!(report.outputs[0].leds, 10);
+    }
+
+    #[test]
+    fn more_leds_than_the_driver_can_send() {
+        let config = OutputConfig {
+            max_leds: Some(8),
+            ..OutputConfig::default()
+        };
+        let report = validate(&[("box_tube", vec![zone("box_tube", 0.0, 10)], config)], 2);
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.errors,
+            [ValidationError::TooManyLeds {
+                output: "box_tube".to_owned(),
+                slots: 10,
+                max: 8,
+            }]
+        );
+    }
+
+    #[test]
+    fn a_zone_listed_twice_is_a_near_duplicate() {
+        let zones = vec![
+            zone("underglow_front", 0.0, 3),
+            zone("underglow_front", 0.0, 3),
+        ];
+        let report = validate(&[("underglow", zones, OutputConfig::default())], 2);
+        // It renders, just not as intended
+        assert!(report.is_ok());
+        let [ValidationWarning::NearDuplicates { pairs, count }] = &report.warnings[..] else {
+            panic!("{:?}", report.warnings);
+        };
+        assert_eq!(*count, 3);
+        assert_eq!(pairs.len(), 3);
+    }
+
+    #[test]
+    fn broken_configurations_are_errors() {
+        assert_eq!(
+            validate(&[], 0).errors,
+            [ValidationError::NoWorkers, ValidationError::NoOutputs]
+        );
+
+        let outputs = [
+            (
+                "box_tube",
+                vec![zone("box_tube", 0.0, 1)],
+                OutputConfig::default(),
+            ),
+            (
+                "box_tube",
+                vec![zone("box_tube", 1.0, 1)],
+                OutputConfig::default(),
+            ),
+        ];
+        assert_eq!(
+            validate(&outputs, 2).errors,
+            [ValidationError::DuplicateOutput("box_tube".to_owned())]
+        );
+
+        let nan = Zone::new(
+            "box_tube",
+            [Point {
+                x: f64::NAN,
+                y: 0.0,
+                z: 0.0,
+            }],
+        );
+        assert_eq!(
+            validate(&[("box_tube", vec![nan], OutputConfig::default())], 2).errors,
+            [ValidationError::NonFinitePoint(LedRef {
+                output: "box_tube".to_owned(),
+                slot: 0,
+            })]
+        );
+    }
+
+    #[test]
+    fn empty_outputs_and_low_budgets_are_warnings() {
+        let config = OutputConfig {
+            power_budget: Some(PowerBudget {
+                amps: 0.005,
+                led: Default::default(),
+            }),
+            ..OutputConfig::default()
+        };
+        let outputs = [
+            ("box_tube", vec![], OutputConfig::default()),
+            ("underglow", vec![zone("underglow", 0.0, 10)], config),
+        ];
+        let report = validate(&outputs, 2);
+        assert!(report.is_ok());
+        assert_eq!(
+            report.warnings,
+            [
+                ValidationWarning::EmptyOutput("box_tube".to_owned()),
+                ValidationWarning::BudgetBelowIdle {
+                    output: "underglow".to_owned(),
+                    idle_amps: 0.01,
+                    budget_amps: 0.005,
+                },
+            ]
+        );
+    }
+}